@@ -0,0 +1,159 @@
+use crate::analysis::visit::{walk_expression, Visitor};
+use crate::diagnostic::{Code, Diagnostic};
+use crate::grammar::expression::{Expression, KeywordConstant, Op, Term, UnaryOp};
+use crate::grammar::structure::Class;
+
+/** Warn about constant expressions whose result does not fit in 16 bits */
+pub fn check_class(class: &Class) -> Vec<Diagnostic> {
+    let mut checker = OverflowChecker {
+        diagnostics: vec![],
+    };
+    for subroutine_dec in &class.2 {
+        checker.visit_statements(&subroutine_dec.4 .1);
+    }
+    checker.diagnostics
+}
+
+/** Value of a term known at compile time, with the 16 bit wrapping arithmetic of the VM */
+pub fn value_of_term(term: &Term) -> Option<i16> {
+    match term {
+        Term::IntegerConstant(c, _) => Some(*c as i16),
+        Term::KeywordConstant(KeywordConstant::True, _) => Some(-1),
+        Term::KeywordConstant(KeywordConstant::False | KeywordConstant::Null, _) => Some(0),
+        Term::BracketExpression(exp, _) => value_of(exp),
+        Term::UnaryOpTerm(UnaryOp::Minus, term, _) => Some(value_of_term(term)?.wrapping_neg()),
+        Term::UnaryOpTerm(UnaryOp::Not, term, _) => Some(!value_of_term(term)?),
+        _ => None,
+    }
+}
+
+/** Value of an expression known at compile time */
+pub fn value_of(expression: &Expression) -> Option<i16> {
+    expression
+        .1
+        .iter()
+        .try_fold(value_of_term(&expression.0)?, |x, op_term| {
+            fold(op_term.0, x, value_of_term(&op_term.1)?)
+        })
+}
+
+/** Apply an operator like the VM does, None for a division by zero which is left for the program to report */
+pub fn fold(op: Op, x: i16, y: i16) -> Option<i16> {
+    Some(match op {
+        Op::Add => x.wrapping_add(y),
+        Op::Sub => x.wrapping_sub(y),
+        Op::Mul => x.wrapping_mul(y),
+        Op::Div if y == 0 => None?,
+        Op::Div => x.wrapping_div(y),
+        Op::And => x & y,
+        Op::Or => x | y,
+        Op::Lt => -i16::from(x < y),
+        Op::Gt => -i16::from(x > y),
+        Op::Eq => -i16::from(x == y),
+    })
+}
+
+// Exact result of an arithmetic operation when it does not fit in 16 bits
+fn overflow(op: Op, x: i16, y: i16) -> Option<i32> {
+    let (x, y) = (i32::from(x), i32::from(y));
+    let exact = match op {
+        Op::Add => x + y,
+        Op::Sub => x - y,
+        Op::Mul => x * y,
+        Op::Div if y != 0 => x / y,
+        _ => None?,
+    };
+    i16::try_from(exact).is_err().then_some(exact)
+}
+
+struct OverflowChecker {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Visitor for OverflowChecker {
+    fn visit_expression(&mut self, expression: &Expression) {
+        let mut value = value_of_term(&expression.0);
+        for op_term in &expression.1 {
+            let (Some(x), Some(y)) = (value, value_of_term(&op_term.1)) else {
+                break;
+            };
+            if let Some(exact) = overflow(op_term.0, x, y) {
+                let wrapped = fold(op_term.0, x, y).unwrap_or_default();
+                self.diagnostics.push(
+                    Diagnostic::warning(
+                        Code::ConstantOverflow,
+                        expression.0.span().to(op_term.2),
+                        "constant expression overflows",
+                    )
+                    .with_label(format!("{exact} wraps around to {wrapped}"))
+                    .with_note("Jack integers are 16-bit values between -32768 and 32767"),
+                );
+                break;
+            }
+            value = fold(op_term.0, x, y);
+        }
+        walk_expression(self, expression);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_class, value_of};
+    use crate::grammar::statement::Statement;
+    use crate::grammar::structure::Class;
+    use crate::parser::Parser;
+
+    fn parse(expression: &str) -> Class {
+        let source = format!("class A {{ function void f() {{ let x = {expression}; return; }} }}");
+        let content = source.chars().collect::<Vec<_>>();
+        Parser::new(&content, 0).parse().0.unwrap()
+    }
+
+    // The folded value and the label of the overflow warning, if any
+    fn fold(expression: &str) -> (Option<i16>, Option<String>) {
+        let class = parse(expression);
+        let Statement::LetStatement(s) = &class.2[0].4 .1[0] else {
+            unreachable!()
+        };
+        let warnings = check_class(&class);
+        assert!(warnings.len() <= 1);
+        (
+            value_of(&s.2),
+            warnings.first().and_then(|x| x.label.clone()),
+        )
+    }
+
+    #[test]
+    fn limits() {
+        assert_eq!(fold("32767"), (Some(32767), None));
+        assert_eq!(fold("-32767"), (Some(-32767), None));
+        assert_eq!(fold("-32767 - 1"), (Some(-32768), None));
+        assert_eq!(fold("32766 + 1"), (Some(32767), None));
+        assert_eq!(fold("~32767"), (Some(-32768), None));
+        assert_eq!(fold("100 * 327"), (Some(32700), None));
+    }
+
+    #[test]
+    fn wrap_around() {
+        let wraps = |exact: i32, wrapped: i16| {
+            (
+                Some(wrapped),
+                Some(format!("{exact} wraps around to {wrapped}")),
+            )
+        };
+        assert_eq!(fold("32767 + 1"), wraps(32768, -32768));
+        assert_eq!(fold("-32767 - 2"), wraps(-32769, 32767));
+        assert_eq!(fold("200 * 200"), wraps(40000, -25536));
+        assert_eq!(fold("(-32767 - 1) / -1"), wraps(32768, -32768));
+        // Negating the smallest value wraps without an operator to report
+        assert_eq!(fold("-(-32767 - 1)"), (Some(-32768), None));
+        // Only the first overflow in a chain is reported
+        assert_eq!(
+            fold("32767 + 1 + 32767 + 1"),
+            (Some(0), Some("32768 wraps around to -32768".into()))
+        );
+        // A division by zero is left for the program to report
+        assert_eq!(fold("1 / 0"), (None, None));
+        assert_eq!(fold("x + 32767 + 1"), (None, None));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::analysis::visit::{walk_statement, walk_term, Visitor};
+use crate::diagnostic::{Code, Diagnostic};
+use crate::grammar::expression::{Expression, KeywordConstant, Term};
+use crate::grammar::statement::Statement;
+use crate::grammar::structure::{Class, ReturnType, SubroutineDec, SubroutineType, Type};
+
+/** Check that constructors create an object of their class and that functions do not use `this` */
+pub fn check_class(class: &Class) -> Vec<Diagnostic> {
+    let mut checker = ThisChecker {
+        subroutine_dec: None,
+        diagnostics: vec![],
+    };
+    for subroutine_dec in &class.2 {
+        if subroutine_dec.0 == SubroutineType::Constructor {
+            checker.check_return_type(class, subroutine_dec);
+        }
+        if subroutine_dec.0 != SubroutineType::Method {
+            checker.subroutine_dec = Some(subroutine_dec);
+            checker.visit_statements(&subroutine_dec.4 .1);
+        }
+    }
+    checker.diagnostics
+}
+
+struct ThisChecker<'a> {
+    /**the constructor or function being checked*/
+    subroutine_dec: Option<&'a SubroutineDec>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ThisChecker<'_> {
+    fn check_return_type(&mut self, class: &Class, subroutine_dec: &SubroutineDec) {
+        let class_name = &class.0;
+        let found = match &subroutine_dec.1 {
+            ReturnType::Base(Type::ClassName(name)) if name == class_name => return,
+            ReturnType::Base(tp) => tp.to_string(),
+            ReturnType::Void => "void".into(),
+        };
+        self.diagnostics.push(
+            Diagnostic::error(
+                Code::InvalidConstructor,
+                subroutine_dec.2 .1,
+                format!(
+                    "constructor `{}` must return `{}`, not `{found}`",
+                    subroutine_dec.2 .0, class_name.0
+                ),
+            )
+            .with_label(format!("declared to return `{found}`"))
+            .with_note(format!(
+                "write it as `constructor {} {}(...)`",
+                class_name.0, subroutine_dec.2 .0
+            )),
+        );
+    }
+}
+
+impl Visitor for ThisChecker<'_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        let subroutine_dec = self.subroutine_dec.unwrap();
+        if let Statement::ReturnStatement(s) = statement {
+            match &s.0 {
+                // A missing value is reported by the type checker
+                Some(exp) if subroutine_dec.0 == SubroutineType::Constructor && !is_this(exp) => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            Code::InvalidConstructor,
+                            exp.2,
+                            format!("constructor `{}` must return `this`", subroutine_dec.2 .0),
+                        )
+                        .with_label("expected `this`")
+                        .with_note("a constructor returns the object it has just allocated"),
+                    );
+                    // The value itself is not checked for `this` again
+                    return;
+                }
+                _ => {}
+            }
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_term(&mut self, term: &Term) {
+        let subroutine_dec = self.subroutine_dec.unwrap();
+        if let Term::KeywordConstant(KeywordConstant::This, span) = term {
+            if subroutine_dec.0 == SubroutineType::Function {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        Code::ThisInFunction,
+                        *span,
+                        format!(
+                            "`this` cannot be used in function `{}`",
+                            subroutine_dec.2 .0
+                        ),
+                    )
+                    .with_label("there is no object in a function")
+                    .with_secondary(subroutine_dec.2 .1, "this is a function")
+                    .with_note("declare it as a method to use `this`"),
+                );
+            }
+        }
+        walk_term(self, term);
+    }
+}
+
+fn is_this(expression: &Expression) -> bool {
+    if !expression.1.is_empty() {
+        return false;
+    }
+    match &expression.0 {
+        Term::KeywordConstant(KeywordConstant::This, _) => true,
+        Term::BracketExpression(exp, _) => is_this(exp),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_class;
+    use crate::compiler::Compiler;
+    use crate::diagnostic::Code;
+    use crate::grammar::structure::Class;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Class {
+        let content = source.chars().collect::<Vec<_>>();
+        Parser::new(&content, 0).parse().0.unwrap()
+    }
+
+    fn check(body: &str) -> Vec<Code> {
+        let class = parse(&format!("class Ball {{ field int x; {body} }}"));
+        check_class(&class).iter().map(|x| x.code).collect()
+    }
+
+    #[test]
+    fn constructors_return_this() {
+        assert!(check("constructor Ball new() { let x = 1; return this; }").is_empty());
+        assert!(check("constructor Ball new() { return (this); }").is_empty());
+        let other = "constructor Ball new() { return x; }";
+        assert_eq!(check(other), [Code::InvalidConstructor]);
+        let both = "constructor Ball new() { if (x) { return null; } return Ball.new(); }";
+        assert_eq!(
+            check(both),
+            [Code::InvalidConstructor, Code::InvalidConstructor]
+        );
+        // The declared type must be the class itself
+        let int = "constructor int new() { return this; }";
+        assert_eq!(check(int), [Code::InvalidConstructor]);
+        assert_eq!(
+            check("constructor void new() { return this; }"),
+            [Code::InvalidConstructor]
+        );
+    }
+
+    #[test]
+    fn this_in_functions() {
+        assert_eq!(
+            check("function Ball f() { return this; }"),
+            [Code::ThisInFunction]
+        );
+        assert!(check("method Ball f() { return this; }").is_empty());
+    }
+
+    #[test]
+    fn constructors_allocate() {
+        // Every constructor allocates its object, even for a class without fields
+        for (fields, size) in [("", 1), ("field int x, y; field Ball next;", 3)] {
+            let source =
+                format!("class Ball {{ {fields} constructor Ball new() {{ return this; }} }}");
+            let commands = Compiler::new().compile_class(parse(&source));
+            let commands = commands.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            assert_eq!(
+                commands[1..4],
+                [
+                    format!("push constant {size}"),
+                    "call Memory.alloc 1".into(),
+                    "pop pointer 0".into()
+                ]
+            );
+        }
+    }
+}
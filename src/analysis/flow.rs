@@ -0,0 +1,146 @@
+use crate::analysis::constant::value_of;
+use crate::diagnostic::{Code, Diagnostic};
+use crate::grammar::statement::{Statement, Statements};
+use crate::grammar::structure::{Class, ReturnType};
+
+/** Report subroutines that can end without returning a value and statements that never run */
+pub fn check_class(class: &Class) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for subroutine_dec in &class.2 {
+        let body = &subroutine_dec.4;
+        check_statements(&body.1, &mut diagnostics);
+        if let ReturnType::Base(tp) = &subroutine_dec.1 {
+            if !terminates(&body.1) {
+                // Point at the closing brace where the subroutine falls off
+                let mut end = body.2;
+                end.start = end.end;
+                end.start.offset = end.start.offset.saturating_sub(1);
+                end.start.column = end.start.column.saturating_sub(1);
+                diagnostics.push(
+                    Diagnostic::error(
+                        Code::MissingReturn,
+                        subroutine_dec.2 .1,
+                        format!("not all paths of `{}` return a value", subroutine_dec.2 .0),
+                    )
+                    .with_label(format!("expected to return `{tp}`"))
+                    .with_secondary(end, "can reach the end of the body")
+                    .with_note("add a `return` statement at the end of the body"),
+                );
+            }
+        }
+    }
+    diagnostics
+}
+
+/** Whether control never reaches the end of the statements */
+pub fn terminates(statements: &Statements) -> bool {
+    statements.iter().any(statement_terminates)
+}
+
+fn statement_terminates(statement: &Statement) -> bool {
+    match statement {
+        Statement::ReturnStatement(_) => true,
+        // `true` and `false` always take the same branch and the compiler drops the other one,
+        // any other value is only known to be true when it is -1 at run time
+        Statement::IfStatement(s) => match (value_of(&s.0), &s.2) {
+            (Some(0), else_statements) => else_statements.as_ref().is_some_and(terminates),
+            (Some(-1), _) => terminates(&s.1),
+            (_, Some(else_statements)) => terminates(&s.1) && terminates(else_statements),
+            (_, None) => false,
+        },
+        // There is no break in Jack so a loop on `true` never ends
+        Statement::WhileStatement(s) => value_of(&s.0) == Some(-1),
+        Statement::LetStatement(_) | Statement::DoStatement(_) => false,
+    }
+}
+
+fn check_statements(statements: &Statements, diagnostics: &mut Vec<Diagnostic>) {
+    for statement in statements {
+        match statement {
+            Statement::IfStatement(s) => {
+                check_statements(&s.1, diagnostics);
+                if let Some(else_statements) = &s.2 {
+                    check_statements(else_statements, diagnostics);
+                }
+            }
+            Statement::WhileStatement(s) => check_statements(&s.1, diagnostics),
+            _ => {}
+        }
+    }
+    let Some(position) = statements.iter().position(statement_terminates) else {
+        return;
+    };
+    let unreachable = &statements[position + 1..];
+    if let (Some(first), Some(last)) = (unreachable.first(), unreachable.last()) {
+        let label = match &statements[position] {
+            Statement::ReturnStatement(_) => "any code following this `return` is unreachable",
+            Statement::WhileStatement(_) => "any code following this loop is unreachable",
+            _ => "any code following this statement is unreachable",
+        };
+        diagnostics.push(
+            Diagnostic::warning(
+                Code::UnreachableStatement,
+                first.span().to(last.span()),
+                "unreachable statement",
+            )
+            .with_label("unreachable statement")
+            .with_secondary(statements[position].span(), label),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_class;
+    use crate::diagnostic::Code;
+    use crate::parser::Parser;
+
+    // The codes of the diagnostics for a subroutine `int f(int x)` with the given body
+    fn check(body: &str) -> Vec<Code> {
+        let source = format!("class A {{ function int f(int x) {{ {body} }} }}");
+        let content = source.chars().collect::<Vec<_>>();
+        let class = Parser::new(&content, 0).parse().0.unwrap();
+        check_class(&class).iter().map(|x| x.code).collect()
+    }
+
+    #[test]
+    fn missing_return() {
+        assert_eq!(check("let x = 1;"), [Code::MissingReturn]);
+        assert_eq!(check("if (x) { return 1; }"), [Code::MissingReturn]);
+        assert_eq!(check("while (x) { return 1; }"), [Code::MissingReturn]);
+        assert!(check("if (x) { return 1; } else { return 2; }").is_empty());
+        assert!(check("if (x) { let x = 1; } return x;").is_empty());
+        // Void subroutines may end without a return
+        let content = "class A { function void f() { } }"
+            .chars()
+            .collect::<Vec<_>>();
+        let class = Parser::new(&content, 0).parse().0.unwrap();
+        assert!(check_class(&class).is_empty());
+    }
+
+    #[test]
+    fn unreachable_code() {
+        assert_eq!(check("return 1; let x = 2;"), [Code::UnreachableStatement]);
+        let nested = "if (x) { return 1; let x = 2; } return 3;";
+        assert_eq!(check(nested), [Code::UnreachableStatement]);
+        let both = "if (x) { return 1; } else { return 2; } return 3;";
+        assert_eq!(check(both), [Code::UnreachableStatement]);
+        assert!(check("if (x) { return 1; } return 2;").is_empty());
+    }
+
+    #[test]
+    fn constant_conditions() {
+        // Only true (-1) and false (0) always take the same branch
+        assert!(check("if (true) { return 1; }").is_empty());
+        assert!(check("if (~0) { return 1; }").is_empty());
+        assert!(check("if (false) { let x = 1; } else { return 1; }").is_empty());
+        assert_eq!(check("if (false) { return 1; }"), [Code::MissingReturn]);
+        assert_eq!(check("if (1) { return 1; }"), [Code::MissingReturn]);
+        // A loop on true never ends, the one on another constant may
+        assert!(check("while (true) { let x = x + 1; }").is_empty());
+        let after_loop = "while (true) { let x = 1; } return 1;";
+        assert_eq!(check(after_loop), [Code::UnreachableStatement]);
+        assert_eq!(check("while (1) { let x = 1; }"), [Code::MissingReturn]);
+        assert_eq!(check("while (false) { return 1; }"), [Code::MissingReturn]);
+    }
+}
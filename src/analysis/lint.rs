@@ -0,0 +1,197 @@
+use crate::analysis::visit::{walk_call, walk_statement, walk_term, Visitor};
+use crate::diagnostic::{Code, Diagnostic};
+use crate::grammar::expression::{SubroutineCall, Term};
+use crate::grammar::statement::Statement;
+use crate::grammar::structure::Class;
+use crate::grammar::terminal::Identifier;
+use crate::symbol_table::{Kind, SymbolTable};
+
+/** Warn about variables, parameters, fields and statics that are never used */
+pub fn check_class(class: &Class) -> Vec<Diagnostic> {
+    let mut lint = Lint {
+        class_symbol_table: SymbolTable::for_class(class),
+        subroutine_symbol_table: SymbolTable::new(),
+        diagnostics: vec![],
+    };
+    for subroutine_dec in &class.2 {
+        lint.subroutine_symbol_table = SymbolTable::for_subroutine(class, subroutine_dec);
+        lint.visit_statements(&subroutine_dec.4 .1);
+        lint.diagnostics
+            .append(&mut unused(&lint.subroutine_symbol_table));
+    }
+    // Class variables are private so every use is in this class
+    lint.diagnostics
+        .append(&mut unused(&lint.class_symbol_table));
+    lint.diagnostics
+}
+
+struct Lint {
+    class_symbol_table: SymbolTable,
+    subroutine_symbol_table: SymbolTable,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Lint {
+    fn read(&mut self, var: &Identifier) {
+        if !self.subroutine_symbol_table.record_read(&var.0) {
+            self.class_symbol_table.record_read(&var.0);
+        }
+    }
+
+    fn write(&mut self, var: &Identifier) {
+        if !self.subroutine_symbol_table.record_write(&var.0) {
+            self.class_symbol_table.record_write(&var.0);
+        }
+    }
+}
+
+fn unused(table: &SymbolTable) -> Vec<Diagnostic> {
+    table
+        .variables()
+        // A leading underscore marks the variable as intentionally unused
+        .filter(|(name, _)| *name != "this" && !name.starts_with('_'))
+        .filter_map(|(name, var)| {
+            let what = match var.kind {
+                Kind::Var => "variable",
+                Kind::Arg => "parameter",
+                Kind::Field => "field",
+                Kind::Static => "static variable",
+            };
+            let (code, message, label) = match (var.reads, var.writes) {
+                (0, 0) => (
+                    Code::UnusedVariable,
+                    format!("unused {what} `{name}`"),
+                    "never used",
+                ),
+                (0, _) if var.kind != Kind::Arg => (
+                    Code::NeverRead,
+                    format!("{what} `{name}` is assigned but never read"),
+                    "only assigned to",
+                ),
+                (0, _) => (
+                    Code::UnusedVariable,
+                    format!("unused {what} `{name}`"),
+                    "the value passed in is never read",
+                ),
+                _ => None?,
+            };
+            let diagnostic = Diagnostic::warning(code, var.span, message)
+                .with_label(label)
+                .with_note(format!("rename it to `_{name}` if this is intentional"));
+            Some(diagnostic)
+        })
+        .collect()
+}
+
+impl Visitor for Lint {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let Statement::LetStatement(s) = statement {
+            // Storing into an array element uses the array
+            if s.1.is_some() {
+                self.read(&s.0);
+            } else {
+                self.write(&s.0);
+            }
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_term(&mut self, term: &Term) {
+        if let Term::VarName(var) | Term::VarNameIndex(var, ..) = term {
+            self.read(var);
+        }
+        walk_term(self, term);
+    }
+
+    fn visit_call(&mut self, call: &SubroutineCall) {
+        // Class names are not in the tables and are ignored
+        if let Some(receiver) = &call.0 {
+            self.read(receiver);
+        }
+        walk_call(self, call);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_class;
+    use crate::diagnostic::Code;
+    use crate::parser::Parser;
+
+    // The codes and messages for the class with the given body, in source order
+    fn check(body: &str) -> Vec<(Code, String)> {
+        let source = format!("class A {{ {body} }}");
+        let content = source.chars().collect::<Vec<_>>();
+        let class = Parser::new(&content, 0).parse().0.unwrap();
+        let mut diagnostics = check_class(&class);
+        diagnostics.sort_by_key(|x| x.span.start.offset);
+        diagnostics
+            .iter()
+            .map(|x| (x.code, x.message.clone()))
+            .collect()
+    }
+
+    fn function(body: &str) -> Vec<(Code, String)> {
+        check(&format!(
+            "function void f(int p) {{ var int x; {body} return; }}"
+        ))
+    }
+
+    #[test]
+    fn unused_variables_and_parameters() {
+        assert_eq!(
+            function(""),
+            [
+                (Code::UnusedVariable, "unused parameter `p`".into()),
+                (Code::UnusedVariable, "unused variable `x`".into())
+            ]
+        );
+        assert!(function("let x = p; do Output.printInt(x);").is_empty());
+        // A parameter that is only assigned still has its value never read
+        assert_eq!(
+            function("let p = 1; let x = 2; do Output.printInt(x);"),
+            [(Code::UnusedVariable, "unused parameter `p`".into())]
+        );
+    }
+
+    #[test]
+    fn never_read() {
+        assert_eq!(
+            function("let x = p;"),
+            [(
+                Code::NeverRead,
+                "variable `x` is assigned but never read".into()
+            )]
+        );
+        // Storing into an array element reads the array
+        assert!(function("let x = p; let x[0] = 1;").is_empty());
+    }
+
+    #[test]
+    fn fields_and_statics() {
+        assert_eq!(
+            check("field int a; static int b; method void f() { return; }"),
+            [
+                (Code::UnusedVariable, "unused field `a`".into()),
+                (Code::UnusedVariable, "unused static variable `b`".into())
+            ]
+        );
+        assert_eq!(
+            check("static int b; function void f() { let b = 1; return; }"),
+            [(
+                Code::NeverRead,
+                "static variable `b` is assigned but never read".into()
+            )]
+        );
+        let used = "field Array a; method void f() { do a.dispose(); return; }";
+        assert!(check(used).is_empty());
+    }
+
+    #[test]
+    fn underscore_silences() {
+        assert!(
+            check("field int _a; function void f(int _p) { var int _x; let _x = 1; return; }")
+                .is_empty()
+        );
+    }
+}
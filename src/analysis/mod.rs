@@ -0,0 +1,8 @@
+pub mod constant;
+pub mod constructor;
+pub mod flow;
+pub mod lint;
+mod os;
+pub mod program;
+pub mod type_check;
+pub mod visit;
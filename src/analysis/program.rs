@@ -0,0 +1,386 @@
+use crate::analysis::os::OS_CLASSES;
+use crate::analysis::visit::{walk_call, Visitor};
+use crate::diagnostic::{Code, Diagnostic};
+use crate::grammar::expression::SubroutineCall;
+use crate::grammar::structure::{Class, ReturnType, SubroutineType, Type};
+use crate::grammar::terminal::Identifier;
+use crate::parser::Parser;
+use crate::span::Span;
+use crate::symbol_table::SymbolTable;
+use std::collections::HashMap;
+
+pub struct SubroutineSignature {
+    pub kind: SubroutineType,
+    pub return_type: ReturnType,
+    pub params: Vec<Type>,
+    /**location of the declaration, None for the OS subroutines*/
+    pub span: Option<Span>,
+}
+
+pub struct ClassSignature {
+    pub subroutines: HashMap<String, SubroutineSignature>,
+    pub span: Option<Span>,
+    /**false for a class with parse errors, whose subroutines may not all have been read*/
+    pub complete: bool,
+}
+
+/** Every class and subroutine known to the program, including the Jack OS API */
+pub struct ProgramIndex {
+    classes: HashMap<String, ClassSignature>,
+}
+
+impl ProgramIndex {
+    pub fn new() -> Self {
+        let mut res = Self {
+            classes: HashMap::new(),
+        };
+        for source in OS_CLASSES {
+            let content = source.chars().collect::<Vec<_>>();
+            let (class, _) = Parser::new(&content, usize::MAX).parse();
+            let class = class.expect("OS signatures should parse");
+            res.add_class(&class);
+            // Builtins have no source to point at
+            let signature = res.classes.get_mut(&class.0 .0).unwrap();
+            signature.span = None;
+            signature
+                .subroutines
+                .values_mut()
+                .for_each(|x| x.span = None);
+        }
+        res
+    }
+
+    // Classes of the program replace the OS ones with the same name
+    pub fn add_class(&mut self, class: &Class) {
+        let subroutines = class
+            .2
+            .iter()
+            .map(|dec| {
+                let signature = SubroutineSignature {
+                    kind: dec.0,
+                    return_type: dec.1.clone(),
+                    params: dec.3.iter().map(|x| x.0.clone()).collect(),
+                    span: Some(dec.2 .1),
+                };
+                (dec.2 .0.clone(), signature)
+            })
+            .collect();
+        self.classes.insert(
+            class.0 .0.clone(),
+            ClassSignature {
+                subroutines,
+                span: Some(class.0 .1),
+                complete: true,
+            },
+        );
+    }
+
+    // Classes that failed to parse are still known, so that using them is not an error
+    pub fn add_partial_class(&mut self, class: &Class) {
+        self.add_class(class);
+        self.classes.get_mut(&class.0 .0).unwrap().complete = false;
+    }
+
+    // A file that did not even get to its class declaration is known by its name
+    pub fn add_unparsed_class(&mut self, name: &str) {
+        let signature = ClassSignature {
+            subroutines: HashMap::new(),
+            span: None,
+            complete: false,
+        };
+        self.classes.entry(name.to_string()).or_insert(signature);
+    }
+
+    pub fn class(&self, name: &str) -> Option<&ClassSignature> {
+        self.classes.get(name)
+    }
+
+    pub fn subroutine(&self, class: &str, name: &str) -> Option<&SubroutineSignature> {
+        self.classes.get(class)?.subroutines.get(name)
+    }
+
+    /** Find the subroutine a call refers to, variables are looked up with type_of */
+    pub fn resolve<F>(
+        &self,
+        current_class: &str,
+        call: &SubroutineCall,
+        type_of: F,
+    ) -> Option<&SubroutineSignature>
+    where
+        F: Fn(&Identifier) -> Option<Type>,
+    {
+        let class = match &call.0 {
+            None => current_class.to_string(),
+            Some(name) => match type_of(name) {
+                Some(Type::ClassName(class)) => class.0,
+                Some(_) => None?,
+                None => name.0.clone(),
+            },
+        };
+        self.subroutine(&class, &call.1 .0)
+    }
+
+    /** Check that every subroutine called by the class exists and is called the right way */
+    pub fn check_class(&self, class: &Class) -> Vec<Diagnostic> {
+        let mut checker = CallChecker {
+            index: self,
+            class_name: &class.0 .0,
+            class_symbol_table: SymbolTable::for_class(class),
+            subroutine_symbol_table: SymbolTable::new(),
+            diagnostics: vec![],
+        };
+        for var_dec in &class.1 {
+            checker.check_type(&var_dec.1);
+        }
+        for subroutine_dec in &class.2 {
+            if let ReturnType::Base(tp) = &subroutine_dec.1 {
+                checker.check_type(tp);
+            }
+            subroutine_dec
+                .3
+                .iter()
+                .for_each(|x| checker.check_type(&x.0));
+            subroutine_dec
+                .4
+                 .0
+                .iter()
+                .for_each(|x| checker.check_type(&x.0));
+
+            checker.subroutine_symbol_table = SymbolTable::for_subroutine(class, subroutine_dec);
+            checker.visit_statements(&subroutine_dec.4 .1);
+        }
+        checker.diagnostics
+    }
+}
+
+struct CallChecker<'a> {
+    index: &'a ProgramIndex,
+    class_name: &'a str,
+    class_symbol_table: SymbolTable,
+    subroutine_symbol_table: SymbolTable,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl CallChecker<'_> {
+    fn check_type(&mut self, tp: &Type) {
+        if let Type::ClassName(name) = tp {
+            if self.index.class(&name.0).is_none() {
+                self.diagnostics.push(unknown_class(name));
+            }
+        }
+    }
+
+    fn check_call(&mut self, call: &SubroutineCall) {
+        let name = &call.1;
+        // Work out the class being called and whether it is called on an object
+        let (class, receiver) = match &call.0 {
+            None => (self.class_name.to_string(), None),
+            Some(var) => {
+                let tp = self
+                    .subroutine_symbol_table
+                    .type_of(&var.0)
+                    .or_else(|| self.class_symbol_table.type_of(&var.0));
+                match tp {
+                    // Not an object, which is reported when compiling
+                    Some(Type::Int | Type::Char | Type::Boolean) => return,
+                    // Unknown classes are reported at the declaration
+                    Some(Type::ClassName(class)) if self.index.class(&class.0).is_none() => return,
+                    Some(Type::ClassName(class)) => (class.0, Some(var)),
+                    None if self.index.class(&var.0).is_none() => {
+                        self.diagnostics.push(unknown_class(var));
+                        return;
+                    }
+                    None => (var.0.clone(), None),
+                }
+            }
+        };
+        let class_signature = self.index.class(&class).unwrap();
+        let Some(signature) = class_signature.subroutines.get(&name.0) else {
+            // Calls within the class are reported when compiling, and the subroutine may be
+            // one that was not parsed
+            if call.0.is_none() || !class_signature.complete {
+                return;
+            }
+            let mut err = Diagnostic::error(
+                Code::UnknownSubroutine,
+                name.1,
+                format!("class `{class}` has no subroutine named `{}`", name.0),
+            )
+            .with_label("not found in this class");
+            if let Some(span) = class_signature.span {
+                err = err.with_secondary(span, format!("class `{class}` declared here"));
+            }
+            self.diagnostics.push(err);
+            return;
+        };
+
+        let full_name = format!("{class}.{}", name.0);
+        match (&call.0, receiver, signature.kind) {
+            (Some(class_name), None, SubroutineType::Method) => {
+                let err = Diagnostic::error(
+                    Code::CallKindMismatch,
+                    call.3,
+                    format!("method `{full_name}` called as a function"),
+                )
+                .with_label(format!("`{}` is a class, not an object", class_name.0))
+                .with_note(format!(
+                    "call the method on an instance of `{}`",
+                    class_name.0
+                ));
+                self.diagnostics.push(with_declaration(err, signature));
+            }
+            (_, Some(var), SubroutineType::Function | SubroutineType::Constructor) => {
+                let err = Diagnostic::error(
+                    Code::CallKindMismatch,
+                    call.3,
+                    format!("`{full_name}` is not a method"),
+                )
+                .with_label(format!("called on the object `{}`", var.0))
+                .with_note(format!("call it as `{full_name}(...)` instead"));
+                self.diagnostics.push(with_declaration(err, signature));
+            }
+            _ => {}
+        }
+
+        if signature.params.len() != call.2.len() {
+            let plural = |n: usize| if n == 1 { "" } else { "s" };
+            let err = Diagnostic::error(
+                Code::ArgumentCount,
+                call.3,
+                format!(
+                    "`{full_name}` takes {} argument{} but {} {} supplied",
+                    signature.params.len(),
+                    plural(signature.params.len()),
+                    call.2.len(),
+                    if call.2.len() == 1 { "was" } else { "were" }
+                ),
+            )
+            .with_label(format!(
+                "expected {} argument{}",
+                signature.params.len(),
+                plural(signature.params.len())
+            ));
+            self.diagnostics.push(with_declaration(err, signature));
+        }
+    }
+}
+
+impl Visitor for CallChecker<'_> {
+    fn visit_call(&mut self, call: &SubroutineCall) {
+        self.check_call(call);
+        walk_call(self, call);
+    }
+}
+
+fn unknown_class(name: &Identifier) -> Diagnostic {
+    Diagnostic::error(
+        Code::UnknownClass,
+        name.1,
+        format!("cannot find class `{}`", name.0),
+    )
+    .with_label("not found in this program")
+}
+
+fn with_declaration(diagnostic: Diagnostic, signature: &SubroutineSignature) -> Diagnostic {
+    match signature.span {
+        Some(span) => diagnostic.with_secondary(span, "declared here"),
+        None => diagnostic,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProgramIndex;
+    use crate::diagnostic::Code;
+    use crate::grammar::structure::Class;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Class {
+        let content = source.chars().collect::<Vec<_>>();
+        Parser::new(&content, 0).parse().0.unwrap()
+    }
+
+    fn codes(index: &ProgramIndex, source: &str) -> Vec<Code> {
+        index
+            .check_class(&parse(source))
+            .iter()
+            .map(|x| x.code)
+            .collect()
+    }
+
+    #[test]
+    fn lookups() {
+        let mut index = ProgramIndex::new();
+        index.add_class(&parse("class Ball { method void move() { return; } }"));
+        assert!(index.class("Ball").is_some());
+        assert!(index.subroutine("Ball", "move").is_some());
+        assert!(index.subroutine("Ball", "jump").is_none());
+        assert!(index.class("Bat").is_none());
+        assert!(index.subroutine("Bat", "move").is_none());
+        // The OS classes are there from the start and have no location
+        assert!(index.class("Math").unwrap().span.is_none());
+        assert!(index.subroutine("Math", "multiply").is_some());
+    }
+
+    #[test]
+    fn duplicate_classes() {
+        // A program class replaces the OS class with the same name
+        let mut index = ProgramIndex::new();
+        index.add_class(&parse(
+            "class Math { function int double(int x) { return x + x; } }",
+        ));
+        assert!(index.class("Math").unwrap().span.is_some());
+        assert!(index.subroutine("Math", "double").is_some());
+        assert!(index.subroutine("Math", "multiply").is_none());
+        // The last class added with a name is the one kept
+        index.add_class(&parse(
+            "class Math { function int half(int x) { return x / 2; } }",
+        ));
+        assert!(index.subroutine("Math", "half").is_some());
+        assert!(index.subroutine("Math", "double").is_none());
+    }
+
+    #[test]
+    fn unknown_classes_and_subroutines() {
+        let mut index = ProgramIndex::new();
+        index.add_class(&parse("class Ball { method void move() { return; } }"));
+        let check = |body: &str| {
+            let source =
+                format!("class Main {{ function void main() {{ var Ball b; {body} return; }} }}");
+            codes(&index, &source)
+        };
+        assert!(check("do b.move();").is_empty());
+        assert!(check("do Output.printInt(1);").is_empty());
+        assert_eq!(check("do Bat.move();"), [Code::UnknownClass]);
+        assert_eq!(check("do Ball.jump();"), [Code::UnknownSubroutine]);
+        assert_eq!(check("do b.jump();"), [Code::UnknownSubroutine]);
+        assert_eq!(
+            check("do Output.printNumber(1);"),
+            [Code::UnknownSubroutine]
+        );
+        assert_eq!(check("do Ball.move();"), [Code::CallKindMismatch]);
+        assert_eq!(check("do b.move(1);"), [Code::ArgumentCount]);
+        // Unknown types are reported at the declaration only
+        let source = "class Main { field Bat b; method void f() { do b.move(); return; } }";
+        assert_eq!(codes(&index, source), [Code::UnknownClass]);
+    }
+
+    #[test]
+    fn classes_with_parse_errors() {
+        // Whatever was read of a class with errors is known, and nothing is reported missing
+        let content = "class Ball { method void move() { return; } method void jump() { let } }"
+            .chars()
+            .collect::<Vec<_>>();
+        let (class, diagnostics) = Parser::new(&content, 0).parse();
+        assert!(!diagnostics.is_empty());
+        let mut index = ProgramIndex::new();
+        index.add_partial_class(&class.unwrap());
+        index.add_unparsed_class("Bat");
+        let source = "class Main { function void main() { var Ball b; var Bat c; \
+            do b.move(1); do b.jump(); do b.bounce(); do c.swing(); do Net.raise(); return; } }";
+        assert_eq!(
+            codes(&index, source),
+            [Code::ArgumentCount, Code::UnknownClass]
+        );
+    }
+}
@@ -0,0 +1,440 @@
+use crate::analysis::program::ProgramIndex;
+use crate::diagnostic::{Code, Diagnostic, Severity};
+use crate::grammar::expression::{Expression, KeywordConstant, Op, SubroutineCall, Term, UnaryOp};
+use crate::grammar::statement::{Statement, Statements};
+use crate::grammar::structure::{Class, ClassName, ReturnType, SubroutineType, Type};
+use crate::grammar::terminal::Identifier;
+use crate::span::Span;
+use crate::symbol_table::SymbolTable;
+use std::fmt::{Display, Formatter};
+
+/** Type of an expression as far as it can be known statically */
+#[derive(Debug, Clone, PartialEq)]
+enum Inferred {
+    Type(Type),
+    /**null can be assigned to any object*/
+    Null,
+    /**array elements and values returned from subroutines*/
+    Unknown,
+}
+
+impl Display for Inferred {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Inferred::Type(tp) => write!(f, "{tp}"),
+            Inferred::Null => write!(f, "null"),
+            Inferred::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl Inferred {
+    fn is_numeric(&self) -> bool {
+        match self {
+            Inferred::Type(tp) => matches!(tp, Type::Int | Type::Char) || is_array(tp),
+            Inferred::Null => false,
+            Inferred::Unknown => true,
+        }
+    }
+
+    fn is_boolean(&self) -> bool {
+        matches!(self, Inferred::Type(Type::Boolean) | Inferred::Unknown)
+    }
+}
+
+pub struct TypeChecker<'a> {
+    /**report mismatches as errors instead of warnings*/
+    strict: bool,
+    /**signatures of the subroutines when the whole program is known*/
+    index: Option<&'a ProgramIndex>,
+    class_name: ClassName,
+    return_type: ReturnType,
+    /**constructors are checked to return `this` instead*/
+    constructor: bool,
+    class_symbol_table: SymbolTable,
+    subroutine_symbol_table: SymbolTable,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn new(strict: bool, index: Option<&'a ProgramIndex>) -> Self {
+        Self {
+            strict,
+            index,
+            class_name: Identifier("".into(), Span::default()),
+            return_type: ReturnType::Void,
+            constructor: false,
+            class_symbol_table: SymbolTable::new(),
+            subroutine_symbol_table: SymbolTable::new(),
+            diagnostics: vec![],
+        }
+    }
+
+    pub fn check_class(&mut self, class: &Class) -> Vec<Diagnostic> {
+        self.class_name = class.0.clone();
+        self.class_symbol_table = SymbolTable::for_class(class);
+        for subroutine_dec in &class.2 {
+            self.return_type = subroutine_dec.1.clone();
+            self.constructor = subroutine_dec.0 == SubroutineType::Constructor;
+            self.subroutine_symbol_table = SymbolTable::for_subroutine(class, subroutine_dec);
+            self.check_statements(&subroutine_dec.4 .1);
+        }
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    fn check_statements(&mut self, statements: &Statements) {
+        for statement in statements {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::LetStatement(s) => {
+                let value = self.infer_expression(&s.2);
+                if let Some(idx) = &s.1 {
+                    // Any value can be stored in an array
+                    self.infer_array_access(&s.0, idx);
+                } else if let Some(tp) = self.type_of(&s.0) {
+                    if !assignable(&tp, &value) {
+                        self.mismatch(
+                            s.2 .2,
+                            format!("cannot assign `{value}` to `{}` of type `{tp}`", s.0 .0),
+                            format!("expected `{tp}`"),
+                        );
+                    }
+                }
+            }
+            Statement::IfStatement(s) => {
+                self.check_condition(&s.0);
+                self.check_statements(&s.1);
+                if let Some(else_statements) = &s.2 {
+                    self.check_statements(else_statements);
+                }
+            }
+            Statement::WhileStatement(s) => {
+                self.check_condition(&s.0);
+                self.check_statements(&s.1);
+            }
+            Statement::DoStatement(s) => {
+                self.infer_call(&s.0);
+            }
+            Statement::ReturnStatement(s) => match (&self.return_type, &s.0) {
+                (ReturnType::Void, Some(exp)) => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            Code::ReturnValueInVoid,
+                            exp.2,
+                            "void subroutine cannot return a value",
+                        )
+                        .with_label("remove this value"),
+                    );
+                }
+                (ReturnType::Base(tp), None) => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            Code::MissingReturnValue,
+                            s.1,
+                            format!("expected a return value of type `{tp}`"),
+                        )
+                        .with_label("missing value"),
+                    );
+                }
+                (ReturnType::Base(tp), Some(exp)) => {
+                    let tp = tp.clone();
+                    let value = self.infer_expression(exp);
+                    if !self.constructor && !assignable(&tp, &value) {
+                        self.mismatch(
+                            exp.2,
+                            format!("cannot return `{value}` from a subroutine returning `{tp}`"),
+                            format!("expected `{tp}`"),
+                        );
+                    }
+                }
+                (ReturnType::Void, None) => {}
+            },
+        }
+    }
+
+    fn check_condition(&mut self, condition: &Expression) {
+        let value = self.infer_expression(condition);
+        if !value.is_boolean() {
+            let diagnostic = Diagnostic::warning(
+                Code::ConditionNotBoolean,
+                condition.2,
+                format!("condition has type `{value}`, expected `boolean`"),
+            )
+            .with_label("expected `boolean`")
+            .with_note("conditions should be `true` or `false` (-1 or 0), other integers do not behave as true");
+            self.report(diagnostic);
+        }
+    }
+
+    fn infer_expression(&mut self, expression: &Expression) -> Inferred {
+        let mut res = self.infer_term(&expression.0);
+        for op_term in &expression.1 {
+            let rhs = self.infer_term(&op_term.1);
+            res = self.infer_op(op_term.0, res, rhs, op_term.2);
+        }
+        res
+    }
+
+    fn infer_op(&mut self, op: Op, lhs: Inferred, rhs: Inferred, span: Span) -> Inferred {
+        match op {
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Lt | Op::Gt => {
+                for side in [&lhs, &rhs] {
+                    if !side.is_numeric() {
+                        self.mismatch(
+                            span,
+                            format!("cannot apply `{op}` to `{side}`"),
+                            "expected `int`",
+                        );
+                    }
+                }
+                if matches!(op, Op::Lt | Op::Gt) {
+                    Inferred::Type(Type::Boolean)
+                } else {
+                    Inferred::Type(Type::Int)
+                }
+            }
+            Op::And | Op::Or => {
+                // Either logical on booleans or bitwise on numbers
+                if lhs.is_boolean() && rhs.is_boolean() {
+                    if lhs == Inferred::Unknown {
+                        rhs
+                    } else {
+                        lhs
+                    }
+                } else if lhs.is_numeric() && rhs.is_numeric() {
+                    Inferred::Type(Type::Int)
+                } else {
+                    self.mismatch(
+                        span,
+                        format!("cannot apply `{op}` to `{lhs}` and `{rhs}`"),
+                        "operands must both be `boolean` or both be `int`",
+                    );
+                    Inferred::Unknown
+                }
+            }
+            Op::Eq => {
+                let comparable = match (&lhs, &rhs) {
+                    (Inferred::Type(a), b) => assignable(a, b),
+                    (a, Inferred::Type(b)) => assignable(b, a),
+                    _ => true,
+                };
+                if !comparable {
+                    self.mismatch(
+                        span,
+                        format!("cannot compare `{lhs}` with `{rhs}`"),
+                        "mismatched types",
+                    );
+                }
+                Inferred::Type(Type::Boolean)
+            }
+        }
+    }
+
+    fn infer_term(&mut self, term: &Term) -> Inferred {
+        match term {
+            Term::IntegerConstant(..) => Inferred::Type(Type::Int),
+            Term::StringConstant(..) => Inferred::Type(class_type("String")),
+            Term::KeywordConstant(c, _) => match c {
+                KeywordConstant::True | KeywordConstant::False => Inferred::Type(Type::Boolean),
+                KeywordConstant::Null => Inferred::Null,
+                KeywordConstant::This => Inferred::Type(Type::ClassName(self.class_name.clone())),
+            },
+            Term::VarName(var) => self.type_of(var).map_or(Inferred::Unknown, Inferred::Type),
+            Term::VarNameIndex(var, index, _) => self.infer_array_access(var, index),
+            Term::BracketExpression(exp, _) => self.infer_expression(exp),
+            Term::UnaryOpTerm(op, term, span) => {
+                let value = self.infer_term(term);
+                match op {
+                    UnaryOp::Minus => {
+                        if !value.is_numeric() {
+                            self.mismatch(
+                                *span,
+                                format!("cannot negate `{value}`"),
+                                "expected `int`",
+                            );
+                        }
+                        Inferred::Type(Type::Int)
+                    }
+                    UnaryOp::Not => {
+                        if !value.is_numeric() && !value.is_boolean() {
+                            self.mismatch(
+                                *span,
+                                format!("cannot apply `~` to `{value}`"),
+                                "expected `boolean` or `int`",
+                            );
+                        }
+                        value
+                    }
+                }
+            }
+            Term::SubroutineCall(call) => self.infer_call(call),
+        }
+    }
+
+    fn infer_call(&mut self, call: &SubroutineCall) -> Inferred {
+        let args = call
+            .2
+            .iter()
+            .map(|exp| (self.infer_expression(exp), exp.2))
+            .collect::<Vec<_>>();
+        let Some(signature) = self
+            .index
+            .and_then(|index| index.resolve(&self.class_name.0, call, |var| self.type_of(var)))
+        else {
+            return Inferred::Unknown;
+        };
+        for ((value, span), tp) in args.iter().zip(&signature.params) {
+            if !assignable(tp, value) {
+                self.mismatch(
+                    *span,
+                    format!("argument has type `{value}`, expected `{tp}`"),
+                    format!("expected `{tp}`"),
+                );
+            }
+        }
+        match &signature.return_type {
+            ReturnType::Base(tp) => Inferred::Type(tp.clone()),
+            ReturnType::Void => Inferred::Unknown,
+        }
+    }
+
+    fn infer_array_access(&mut self, var: &Identifier, index: &Expression) -> Inferred {
+        if let Some(tp @ (Type::Int | Type::Char | Type::Boolean)) = self.type_of(var) {
+            self.mismatch(
+                var.1,
+                format!("cannot index `{}` of type `{tp}`", var.0),
+                "expected an `Array`",
+            );
+        }
+        let idx = self.infer_expression(index);
+        if !idx.is_numeric() {
+            self.mismatch(
+                index.2,
+                format!("array index has type `{idx}`"),
+                "expected `int`",
+            );
+        }
+        Inferred::Unknown
+    }
+
+    fn type_of(&self, var: &Identifier) -> Option<Type> {
+        self.subroutine_symbol_table
+            .type_of(&var.0)
+            .or_else(|| self.class_symbol_table.type_of(&var.0))
+    }
+
+    fn mismatch(&mut self, span: Span, message: String, label: impl Into<String>) {
+        let diagnostic = Diagnostic::warning(Code::TypeMismatch, span, message).with_label(label);
+        self.report(diagnostic);
+    }
+
+    // Type warnings become errors in strict mode
+    fn report(&mut self, mut diagnostic: Diagnostic) {
+        if self.strict {
+            diagnostic.severity = Severity::Error;
+        }
+        self.diagnostics.push(diagnostic);
+    }
+}
+
+fn class_type(name: &str) -> Type {
+    Type::ClassName(Identifier(name.into(), Span::default()))
+}
+
+// An Array variable often holds one of its elements, which can be anything
+fn is_array(tp: &Type) -> bool {
+    matches!(tp, Type::ClassName(name) if name.0 == "Array")
+}
+
+// Jack is weakly typed: chars are numbers and arrays are untyped like their elements
+fn assignable(target: &Type, value: &Inferred) -> bool {
+    match value {
+        Inferred::Unknown => true,
+        Inferred::Null => matches!(target, Type::ClassName(_)),
+        Inferred::Type(value) => match (target, value) {
+            (Type::Boolean, _) | (_, Type::Boolean) => target == value,
+            (a, b) if is_array(a) || is_array(b) => true,
+            (Type::Int | Type::Char, Type::Int | Type::Char) => true,
+            (a, b) => a == b,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TypeChecker;
+    use crate::analysis::program::ProgramIndex;
+    use crate::diagnostic::{Code, Severity};
+    use crate::grammar::structure::Class;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Class {
+        let content = source.chars().collect::<Vec<_>>();
+        Parser::new(&content, 0).parse().0.unwrap()
+    }
+
+    // The codes of the diagnostics for a function with the given body, next to a class `Ball`
+    fn check(strict: bool, body: &str) -> Vec<(Code, Severity)> {
+        let mut index = ProgramIndex::new();
+        index.add_class(&parse(
+            "class Ball { method void move(int dx, boolean bounce) { return; } }",
+        ));
+        let source = format!(
+            "class Main {{ function void main() {{ \
+             var int i; var boolean b; var char c; var Ball ball; var Array a; {body} return; }} }}"
+        );
+        TypeChecker::new(strict, Some(&index))
+            .check_class(&parse(&source))
+            .iter()
+            .map(|x| (x.code, x.severity))
+            .collect()
+    }
+
+    const CONDITION: (Code, Severity) = (Code::ConditionNotBoolean, Severity::Warning);
+    const MISMATCH: (Code, Severity) = (Code::TypeMismatch, Severity::Warning);
+
+    #[test]
+    fn conditions() {
+        assert!(check(false, "if (b) { let i = 1; }").is_empty());
+        assert!(check(false, "while (i < 10) { let i = i + 1; }").is_empty());
+        assert!(check(false, "if (a[0]) { let i = 1; }").is_empty());
+        assert_eq!(check(false, "if (i) { let i = 1; }"), [CONDITION]);
+        assert_eq!(check(false, "while (1) { let i = 1; }"), [CONDITION]);
+        assert_eq!(check(false, "if (ball) { let i = 1; }"), [CONDITION]);
+    }
+
+    #[test]
+    fn assignments() {
+        assert!(check(false, "let i = c; let c = 65; let b = i = 1;").is_empty());
+        assert!(check(false, "let ball = null; let a = ball; let i = a[i];").is_empty());
+        assert_eq!(check(false, "let i = \"str\";"), [MISMATCH]);
+        assert_eq!(check(false, "let b = 1;"), [MISMATCH]);
+        assert_eq!(check(false, "let i = true;"), [MISMATCH]);
+        assert_eq!(check(false, "let i = null;"), [MISMATCH]);
+        assert_eq!(check(false, "let ball = 1;"), [MISMATCH]);
+        // Strict mode makes them errors
+        assert_eq!(
+            check(true, "let b = 1;"),
+            [(Code::TypeMismatch, Severity::Error)]
+        );
+    }
+
+    #[test]
+    fn arguments() {
+        assert!(check(false, "do ball.move(i, b); do ball.move(c, a[0]);").is_empty());
+        assert!(check(false, "do Output.printString(\"str\");").is_empty());
+        assert_eq!(check(false, "do ball.move(b, b);"), [MISMATCH]);
+        assert_eq!(check(false, "do ball.move(1, 1);"), [MISMATCH]);
+        assert_eq!(check(false, "do ball.move(b, i);"), [MISMATCH, MISMATCH]);
+        assert_eq!(check(false, "do Output.printInt(ball);"), [MISMATCH]);
+        // Without the program the arguments cannot be checked
+        let source = "class Main { function void main() { do Ball.move(true); return; } }";
+        assert!(TypeChecker::new(false, None)
+            .check_class(&parse(source))
+            .is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::grammar::expression::{Expression, SubroutineCall, Term};
+use crate::grammar::statement::{Statement, Statements};
+
+/** Walks the statements of a subroutine, override the methods to inspect the nodes of interest */
+pub trait Visitor {
+    fn visit_statements(&mut self, statements: &Statements) {
+        for statement in statements {
+            self.visit_statement(statement);
+        }
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+
+    fn visit_term(&mut self, term: &Term) {
+        walk_term(self, term);
+    }
+
+    fn visit_call(&mut self, call: &SubroutineCall) {
+        walk_call(self, call);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::LetStatement(s) => {
+            if let Some(idx) = &s.1 {
+                visitor.visit_expression(idx);
+            }
+            visitor.visit_expression(&s.2);
+        }
+        Statement::IfStatement(s) => {
+            visitor.visit_expression(&s.0);
+            visitor.visit_statements(&s.1);
+            if let Some(else_statements) = &s.2 {
+                visitor.visit_statements(else_statements);
+            }
+        }
+        Statement::WhileStatement(s) => {
+            visitor.visit_expression(&s.0);
+            visitor.visit_statements(&s.1);
+        }
+        Statement::DoStatement(s) => visitor.visit_call(&s.0),
+        Statement::ReturnStatement(s) => {
+            if let Some(exp) = &s.0 {
+                visitor.visit_expression(exp);
+            }
+        }
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    visitor.visit_term(&expression.0);
+    for op_term in &expression.1 {
+        visitor.visit_term(&op_term.1);
+    }
+}
+
+pub fn walk_term<V: Visitor + ?Sized>(visitor: &mut V, term: &Term) {
+    match term {
+        Term::VarNameIndex(_, index, _) => visitor.visit_expression(index),
+        Term::BracketExpression(exp, _) => visitor.visit_expression(exp),
+        Term::UnaryOpTerm(_, term, _) => visitor.visit_term(term),
+        Term::SubroutineCall(call) => visitor.visit_call(call),
+        Term::IntegerConstant(..)
+        | Term::StringConstant(..)
+        | Term::KeywordConstant(..)
+        | Term::VarName(_) => {}
+    }
+}
+
+pub fn walk_call<V: Visitor + ?Sized>(visitor: &mut V, call: &SubroutineCall) {
+    for exp in &call.2 {
+        visitor.visit_expression(exp);
+    }
+}
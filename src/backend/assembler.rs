@@ -0,0 +1,364 @@
+use crate::backend::SourceLine;
+use crate::diagnostic::{Code, Diagnostic};
+use crate::span::Span;
+use std::collections::HashMap;
+
+/** First RAM address given to the variables of an assembly program */
+const FIRST_VARIABLE: usize = 16;
+/** Last RAM address for the variables, the screen memory map comes after it */
+const LAST_VARIABLE: usize = 16383;
+/** Number of instructions the ROM can hold */
+const ROM_SIZE: usize = 32768;
+
+/** An instruction with its whitespace removed, so `D = M` is read as `D=M` */
+struct Instruction<'a> {
+    text: String,
+    /**column in the line of each character of the text*/
+    columns: Vec<usize>,
+    line: &'a SourceLine,
+}
+
+impl<'a> Instruction<'a> {
+    fn new(line: &'a SourceLine) -> Self {
+        let mut text = String::new();
+        let mut columns = vec![];
+        for (column, c) in line.text.chars().enumerate() {
+            if !c.is_whitespace() {
+                text.push(c);
+                columns.push(column);
+            }
+        }
+        Self {
+            text,
+            columns,
+            line,
+        }
+    }
+
+    /** Span in the line of the text start..end, given as byte offsets like the slices of the text */
+    fn span_of(&self, start: usize, end: usize) -> Span {
+        // Columns are counted in characters
+        let (start, end) = (
+            self.text[..start].chars().count(),
+            self.text[..end].chars().count(),
+        );
+        let column = |i: usize| {
+            let end = self.line.text.chars().count();
+            self.columns.get(i).copied().unwrap_or(end)
+        };
+        if start == end {
+            self.line.span_of(column(start), column(start))
+        } else {
+            self.line.span_of(column(start), column(end - 1) + 1)
+        }
+    }
+}
+
+/** Translate Hack assembly into the binary text of the `.hack` format, one instruction per line */
+pub fn assemble(lines: &[SourceLine]) -> Result<Vec<String>, Diagnostic> {
+    let instructions = lines.iter().map(Instruction::new).collect::<Vec<_>>();
+    let mut symbols = predefined_symbols();
+    // First pass for the labels since they can be used before they are declared
+    let mut label_spans = HashMap::new();
+    let mut address = 0;
+    for instruction in &instructions {
+        let line = instruction.line;
+        // A label there would also point past the end
+        if address == ROM_SIZE {
+            Err(Diagnostic::error(
+                Code::ProgramTooLarge,
+                line.span,
+                format!("the program does not fit in the {ROM_SIZE} words of the ROM"),
+            )
+            .with_label("this is past the end of the ROM"))?
+        }
+        let Some(label) = instruction.text.strip_prefix('(') else {
+            address += 1;
+            continue;
+        };
+        let Some(label) = label.strip_suffix(')') else {
+            Err(invalid(
+                line.span,
+                "label is missing the closing `)`",
+                "expected `)`",
+            ))?
+        };
+        check_symbol(instruction, label, 1)?;
+        if let Some(&previous) = label_spans.get(label) {
+            Err(invalid(
+                line.span,
+                format!("label `{label}` is declared twice"),
+                "redeclared",
+            )
+            .with_secondary(previous, "first declared here"))?
+        }
+        if symbols.contains_key(label) {
+            Err(invalid(
+                line.span,
+                format!("label `{label}` hides a predefined symbol"),
+                "choose another name",
+            ))?
+        }
+        label_spans.insert(label.to_string(), line.span);
+        symbols.insert(label.to_string(), address);
+    }
+
+    let mut next_variable = FIRST_VARIABLE;
+    let mut res = vec![];
+    for instruction in instructions.iter().filter(|x| !x.text.starts_with('(')) {
+        let text = &instruction.text;
+        let bits = if let Some(value) = text.strip_prefix('@') {
+            if value.starts_with(|x: char| x.is_ascii_digit()) {
+                value
+                    .parse()
+                    .ok()
+                    .filter(|&x| x < ROM_SIZE)
+                    .ok_or_else(|| {
+                        invalid(
+                            instruction.span_of(1, text.len()),
+                            format!("`{value}` is not a valid address"),
+                            "expected a number between 0 and 32767",
+                        )
+                    })?
+            } else if let Some(&address) = symbols.get(value) {
+                address
+            } else {
+                check_symbol(instruction, value, 1)?;
+                // Unknown symbols are variables allocated in order of appearance
+                if next_variable > LAST_VARIABLE {
+                    Err(Diagnostic::error(
+                        Code::ProgramTooLarge,
+                        instruction.span_of(1, text.len()),
+                        format!("no RAM left for the variable `{value}`"),
+                    )
+                    .with_label("the variables would run into the screen memory")
+                    .with_note(format!(
+                        "variables are stored from RAM[{FIRST_VARIABLE}] to RAM[{LAST_VARIABLE}]"
+                    )))?
+                }
+                symbols.insert(value.to_string(), next_variable);
+                next_variable += 1;
+                next_variable - 1
+            }
+        } else {
+            c_instruction(instruction)?
+        };
+        res.push(format!("{bits:016b}"));
+    }
+    Ok(res)
+}
+
+// dest=comp;jump where both dest and jump are optional
+fn c_instruction(instruction: &Instruction) -> Result<usize, Diagnostic> {
+    let text = instruction.text.as_str();
+    let (dest, rest, comp_start) = match text.split_once('=') {
+        Some((dest, rest)) => (dest, rest, dest.len() + 1),
+        None => ("", text, 0),
+    };
+    let (comp, jump) = rest.split_once(';').unwrap_or((rest, ""));
+
+    let mut dest_bits = 0;
+    for c in dest.chars() {
+        let bit = match c {
+            'A' => 0b100,
+            'D' => 0b010,
+            'M' => 0b001,
+            _ => 0b1000,
+        };
+        if dest_bits & bit != 0 || bit == 0b1000 {
+            Err(invalid(
+                instruction.span_of(0, dest.len()),
+                format!("`{dest}` is not a valid destination"),
+                "expected a combination of `A`, `D` and `M`",
+            ))?
+        }
+        dest_bits |= bit;
+    }
+
+    let comp_bits = comp_bits(comp).ok_or_else(|| {
+        let span = instruction.span_of(comp_start, comp_start + comp.len());
+        let message = if comp.is_empty() {
+            "missing computation".to_string()
+        } else {
+            format!("`{comp}` is not a valid computation")
+        };
+        invalid(span, message, "unknown computation")
+            .with_note("the computations are listed in chapter 4 of the book")
+    })?;
+
+    let jump_bits = match jump {
+        "" => 0,
+        "JGT" => 1,
+        "JEQ" => 2,
+        "JGE" => 3,
+        "JLT" => 4,
+        "JNE" => 5,
+        "JLE" => 6,
+        "JMP" => 7,
+        _ => {
+            let start = text.len() - jump.len();
+            Err(invalid(
+                instruction.span_of(start, text.len()),
+                format!("`{jump}` is not a valid jump"),
+                "expected one of JGT, JEQ, JGE, JLT, JNE, JLE or JMP",
+            ))?
+        }
+    };
+    Ok(0b111 << 13 | comp_bits << 6 | dest_bits << 3 | jump_bits)
+}
+
+// The a bit and the six c bits of a computation
+fn comp_bits(comp: &str) -> Option<usize> {
+    // M is used in place of A when the a bit is set
+    let (a, comp) = if comp.contains('M') {
+        if comp.contains('A') {
+            None?
+        }
+        (1, comp.replace('M', "A"))
+    } else {
+        (0, comp.to_string())
+    };
+    let c = match comp.as_str() {
+        "0" if a == 0 => 0b101010,
+        "1" if a == 0 => 0b111111,
+        "-1" if a == 0 => 0b111010,
+        "D" if a == 0 => 0b001100,
+        "A" => 0b110000,
+        "!D" if a == 0 => 0b001101,
+        "!A" => 0b110001,
+        "-D" if a == 0 => 0b001111,
+        "-A" => 0b110011,
+        "D+1" | "1+D" if a == 0 => 0b011111,
+        "A+1" | "1+A" => 0b110111,
+        "D-1" if a == 0 => 0b001110,
+        "A-1" => 0b110010,
+        "D+A" | "A+D" => 0b000010,
+        "D-A" => 0b010011,
+        "A-D" => 0b000111,
+        "D&A" | "A&D" => 0b000000,
+        "D|A" | "A|D" => 0b010101,
+        _ => None?,
+    };
+    Some(a << 6 | c)
+}
+
+// Symbols are letters, digits, `_`, `.`, `$` and `:` and do not start with a digit
+fn check_symbol(instruction: &Instruction, symbol: &str, start: usize) -> Result<(), Diagnostic> {
+    let valid = !symbol.is_empty()
+        && !symbol.starts_with(|x: char| x.is_ascii_digit())
+        && symbol
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || "_.$:".contains(x));
+    if !valid {
+        Err(invalid(
+            instruction.span_of(start, start + symbol.len()),
+            format!("`{symbol}` is not a valid symbol"),
+            "symbols are made of letters, digits, `_`, `.`, `$` and `:`",
+        ))?
+    }
+    Ok(())
+}
+
+fn predefined_symbols() -> HashMap<String, usize> {
+    let mut res = HashMap::from([
+        ("SP".to_string(), 0),
+        ("LCL".to_string(), 1),
+        ("ARG".to_string(), 2),
+        ("THIS".to_string(), 3),
+        ("THAT".to_string(), 4),
+        ("SCREEN".to_string(), 16384),
+        ("KBD".to_string(), 24576),
+    ]);
+    for i in 0..16 {
+        res.insert(format!("R{i}"), i);
+    }
+    res
+}
+
+fn invalid(span: Span, message: impl Into<String>, label: impl Into<String>) -> Diagnostic {
+    Diagnostic::error(Code::InvalidInstruction, span, message).with_label(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+    use crate::backend::source_lines;
+    use crate::diagnostic::{Code, Diagnostic};
+    use crate::source::SourceMap;
+
+    fn run(asm: &str) -> Result<Vec<String>, Diagnostic> {
+        let mut sources = SourceMap::new();
+        let file = sources.add("Test.asm".into(), asm.chars().collect());
+        assemble(&source_lines(sources.get(file).unwrap(), file))
+    }
+
+    fn binary(asm: &str) -> Vec<u16> {
+        let hack = run(asm).unwrap();
+        hack.iter()
+            .map(|x| u16::from_str_radix(x, 2).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn labels() {
+        // Labels take no room and can be used before they are declared
+        let asm = "@END\n0;JMP\n(LOOP)\n@LOOP\n0;JMP\n(END)\n@END\n0;JMP";
+        assert_eq!(binary(asm), [4, 0xEA87, 2, 0xEA87, 4, 0xEA87]);
+        let err = run("(A)\n(A)").unwrap_err();
+        assert_eq!(err.code, Code::InvalidInstruction);
+    }
+
+    #[test]
+    fn variables() {
+        // New symbols get the next free address from 16, labels are not variables
+        let asm = "@i\n@sum\n@i\n(L)\n@L";
+        assert_eq!(binary(asm), [16, 17, 16, 3]);
+    }
+
+    #[test]
+    fn predefined_symbols() {
+        let asm = "@SP\n@LCL\n@ARG\n@THIS\n@THAT\n@R13\n@SCREEN\n@KBD";
+        assert_eq!(binary(asm), [0, 1, 2, 3, 4, 13, 16384, 24576]);
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(
+            binary("D = M\nD ; JGT\nAM = M + 1"),
+            binary("D=M\nD;JGT\nAM=M+1")
+        );
+    }
+
+    #[test]
+    fn columns() {
+        // Errors point at characters, whatever their size in bytes
+        let err = run("D = é + 1").unwrap_err();
+        assert_eq!((err.span.start.column, err.span.end.column), (5, 10));
+        let err = run("AMé = 1").unwrap_err();
+        assert_eq!((err.span.start.column, err.span.end.column), (1, 4));
+        let err = run("0;JMPé").unwrap_err();
+        assert_eq!((err.span.start.column, err.span.end.column), (3, 7));
+    }
+
+    #[test]
+    fn overflow() {
+        let asm = "D=0\n".repeat(32768);
+        assert_eq!(run(&asm).unwrap().len(), 32768);
+        let err = run(&format!("{asm}D=0")).unwrap_err();
+        assert_eq!(
+            (err.code, err.span.start.line),
+            (Code::ProgramTooLarge, 32769)
+        );
+
+        let asm = (16..=16383).map(|i| format!("@v{i}\n")).collect::<String>();
+        assert_eq!(
+            run(&asm).unwrap().last().unwrap(),
+            &format!("{:016b}", 16383)
+        );
+        let err = run(&format!("{asm}@v16\n@extra")).unwrap_err();
+        assert_eq!(
+            (err.code, err.span.start.line),
+            (Code::ProgramTooLarge, 16370)
+        );
+    }
+}
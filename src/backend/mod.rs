@@ -0,0 +1,175 @@
+use crate::backend::translator::Translator;
+use crate::diagnostic::{Code, Diagnostic};
+use crate::source::SourceFile;
+use crate::span::{FileId, Position, Span};
+use crate::vm::VmCommand;
+use std::collections::HashSet;
+
+pub mod assembler;
+pub mod translator;
+
+/** Translate the VM units of a program, given by name, into a single assembly program */
+pub fn translate_program(
+    units: &[(String, Vec<VmCommand>)],
+    bootstrap: bool,
+) -> Result<Vec<String>, Vec<Diagnostic>> {
+    let functions = units
+        .iter()
+        .flat_map(|x| &x.1)
+        .filter_map(|x| match x {
+            VmCommand::Function(name, _) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let mut translator = Translator::new();
+    let mut calls = vec![];
+    if bootstrap {
+        // Programs compiled without the OS start at Main.main
+        let entry = if functions.contains("Sys.init") {
+            "Sys.init"
+        } else {
+            "Main.main"
+        };
+        translator.bootstrap(entry);
+        calls.push((entry, "the bootstrap code"));
+    }
+    for (unit, commands) in units {
+        translator.translate(unit, commands);
+        let mut caller = unit.as_str();
+        for command in commands {
+            match command {
+                VmCommand::Function(name, _) => caller = name,
+                VmCommand::Call(name, _) => calls.push((name, caller)),
+                _ => {}
+            }
+        }
+    }
+    // The assembler would take the label of a missing function for a variable
+    let mut reported = HashSet::new();
+    let undefined = calls
+        .into_iter()
+        .filter(|(name, _)| !functions.contains(name) && reported.insert(*name))
+        .map(|(name, caller)| undefined_function(name, caller))
+        .collect::<Vec<_>>();
+    if undefined.is_empty() {
+        Ok(translator.finish())
+    } else {
+        Err(undefined)
+    }
+}
+
+// VM code has no location in the source, the diagnostic names the caller instead
+fn undefined_function(name: &str, caller: &str) -> Diagnostic {
+    let mut res = Diagnostic::error(
+        Code::UnknownSubroutine,
+        Span::default(),
+        format!("call to undefined function `{name}`"),
+    )
+    .with_note(format!("first called from `{caller}`"));
+    if name.split('.').next().is_some_and(is_os_class) {
+        res =
+            res.with_note("the OS is not built in, put the compiled OS .vm files in the directory");
+    }
+    res
+}
+
+fn is_os_class(class: &str) -> bool {
+    let os = [
+        "Math", "String", "Array", "Output", "Screen", "Keyboard", "Memory", "Sys",
+    ];
+    os.contains(&class)
+}
+
+/** A line of VM or assembly code with the comment and the surrounding whitespace removed */
+pub struct SourceLine {
+    pub text: String,
+    pub span: Span,
+}
+
+impl SourceLine {
+    /** Span of the characters start..end of the text */
+    pub fn span_of(&self, start: usize, end: usize) -> Span {
+        let mut res = self.span;
+        res.start.offset += start;
+        res.start.column += start;
+        res.end = res.start;
+        res.end.offset += end - start;
+        res.end.column += end - start;
+        res
+    }
+}
+
+/** The non-empty lines of a VM or assembly file, both use `//` comments */
+pub fn source_lines(source: &SourceFile, file: FileId) -> Vec<SourceLine> {
+    let mut res = vec![];
+    let mut line = 1;
+    while let (Some(text), Some(start)) = (source.line(line), source.line_start(line)) {
+        let code = text.split("//").next().unwrap_or_default();
+        let trimmed = code.trim_start();
+        let column = code.chars().count() - trimmed.chars().count();
+        let trimmed = trimmed.trim_end();
+        if !trimmed.is_empty() {
+            let position = |column: usize| Position {
+                offset: start + column,
+                line,
+                column: column + 1,
+            };
+            res.push(SourceLine {
+                text: trimmed.into(),
+                span: Span::new(
+                    file,
+                    position(column),
+                    position(column + trimmed.chars().count()),
+                ),
+            });
+        }
+        line += 1;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::translate_program;
+    use crate::diagnostic::Code;
+    use crate::vm::VmCommand;
+
+    fn unit(name: &str, text: &str) -> (String, Vec<VmCommand>) {
+        let commands = text.lines().map(|x| x.trim().parse().unwrap()).collect();
+        (name.into(), commands)
+    }
+
+    #[test]
+    fn undefined_functions() {
+        let main = unit(
+            "Main",
+            "function Main.main 0
+            push constant 7
+            call Output.printInt 1
+            call Output.printInt 1
+            call Main.f 0
+            return",
+        );
+        let errors = translate_program(std::slice::from_ref(&main), true).unwrap_err();
+        let messages = errors
+            .iter()
+            .map(|x| x.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "call to undefined function `Output.printInt`",
+                "call to undefined function `Main.f`"
+            ]
+        );
+        assert!(errors.iter().all(|x| x.code == Code::UnknownSubroutine));
+
+        // The functions of every unit can be called, the OS included
+        let f = unit("Main2", "function Main.f 0\nreturn");
+        let output = unit("Output", "function Output.printInt 0\nreturn");
+        assert!(translate_program(&[main, f, output], true).is_ok());
+
+        let errors = translate_program(&[unit("A", "function A.f 0\nreturn")], true).unwrap_err();
+        assert_eq!(errors[0].message, "call to undefined function `Main.main`");
+    }
+}
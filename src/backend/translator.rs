@@ -0,0 +1,196 @@
+use crate::vm::{ArithmeticOp, Segment, VmCommand};
+
+/** Translates VM code into Hack assembly, one class (VM file) at a time */
+pub struct Translator {
+    /**the assembly generated so far*/
+    res: Vec<String>,
+    /**name of the file being translated, used to name the static variables*/
+    unit: String,
+    /**name of the function being translated, used to scope the labels*/
+    function: String,
+    /**counter to create unique labels for comparisons and return addresses*/
+    label_count: usize,
+}
+
+impl Translator {
+    pub fn new() -> Self {
+        Self {
+            res: vec![],
+            unit: String::new(),
+            function: String::new(),
+            label_count: 0,
+        }
+    }
+
+    /** Set the stack pointer and call the entry point, used when translating a whole program */
+    pub fn bootstrap(&mut self, entry: &str) {
+        self.res.push("// bootstrap".into());
+        self.emit(&["@256", "D=A", "@SP", "M=D"]);
+        self.function = "bootstrap".into();
+        self.call(entry, 0);
+        // The entry point is not supposed to return, stop the machine if it does
+        self.emit(&["(bootstrap$halt)", "@bootstrap$halt", "0;JMP"]);
+    }
+
+    pub fn translate(&mut self, unit: &str, commands: &[VmCommand]) {
+        self.unit = unit.into();
+        for command in commands {
+            self.res.push(format!("// {command}"));
+            self.translate_command(command);
+        }
+    }
+
+    pub fn finish(self) -> Vec<String> {
+        self.res
+    }
+
+    fn translate_command(&mut self, command: &VmCommand) {
+        match command {
+            VmCommand::Push(segment, index) => self.push(*segment, *index),
+            VmCommand::Pop(segment, index) => self.pop(*segment, *index),
+            VmCommand::Arithmetic(op) => match op {
+                ArithmeticOp::Add | ArithmeticOp::Sub | ArithmeticOp::And | ArithmeticOp::Or => {
+                    let op = match op {
+                        ArithmeticOp::Add => "M=D+M",
+                        ArithmeticOp::Sub => "M=M-D",
+                        ArithmeticOp::And => "M=D&M",
+                        _ => "M=D|M",
+                    };
+                    self.emit(&["@SP", "AM=M-1", "D=M", "A=A-1", op]);
+                }
+                ArithmeticOp::Neg | ArithmeticOp::Not => {
+                    let op = if *op == ArithmeticOp::Neg {
+                        "M=-M"
+                    } else {
+                        "M=!M"
+                    };
+                    self.emit(&["@SP", "A=M-1", op]);
+                }
+                ArithmeticOp::Eq | ArithmeticOp::Gt | ArithmeticOp::Lt => {
+                    let jump = format!("D;J{}", op.to_string().to_uppercase());
+                    let label = self.unique_label("cmp");
+                    // Assume true and overwrite with false when the jump is not taken
+                    self.emit(&["@SP", "AM=M-1", "D=M", "A=A-1", "D=M-D", "M=-1"]);
+                    self.emit(&[&format!("@{label}"), &jump, "@SP", "A=M-1", "M=0"]);
+                    self.res.push(format!("({label})"));
+                }
+            },
+            VmCommand::Label(label) => self.res.push(format!("({}${label})", self.function)),
+            VmCommand::Goto(label) => {
+                self.emit(&[&format!("@{}${label}", self.function), "0;JMP"]);
+            }
+            VmCommand::IfGoto(label) => {
+                self.emit(&["@SP", "AM=M-1", "D=M"]);
+                self.emit(&[&format!("@{}${label}", self.function), "D;JNE"]);
+            }
+            VmCommand::Function(name, locals) => {
+                self.function = name.clone();
+                self.res.push(format!("({name})"));
+                for _ in 0..*locals {
+                    self.emit(&["@SP", "AM=M+1", "A=A-1", "M=0"]);
+                }
+            }
+            VmCommand::Call(name, args) => self.call(name, *args),
+            VmCommand::Return => self.ret(),
+        }
+    }
+
+    fn push(&mut self, segment: Segment, index: u16) {
+        match segment {
+            Segment::Constant => self.emit(&[&format!("@{index}"), "D=A"]),
+            Segment::Local | Segment::Argument | Segment::This | Segment::That => {
+                let base = base_of(segment);
+                self.emit(&[&format!("@{index}"), "D=A", base, "A=D+M", "D=M"]);
+            }
+            _ => {
+                let address = self.address_of(segment, index);
+                self.emit(&[&address, "D=M"]);
+            }
+        }
+        self.push_d();
+    }
+
+    fn pop(&mut self, segment: Segment, index: u16) {
+        match segment {
+            Segment::Local | Segment::Argument | Segment::This | Segment::That => {
+                // Keep the target address in R13 while the value is popped
+                let base = base_of(segment);
+                self.emit(&[&format!("@{index}"), "D=A", base, "D=D+M", "@R13", "M=D"]);
+                self.emit(&["@SP", "AM=M-1", "D=M", "@R13", "A=M", "M=D"]);
+            }
+            _ => {
+                let address = self.address_of(segment, index);
+                self.emit(&["@SP", "AM=M-1", "D=M", &address, "M=D"]);
+            }
+        }
+    }
+
+    // A-instruction for the segments mapped to fixed addresses, the parser checks the index
+    fn address_of(&self, segment: Segment, index: u16) -> String {
+        match segment {
+            Segment::Static => format!("@{}.{index}", self.unit),
+            Segment::Pointer => format!("@{}", 3 + index),
+            _ => format!("@{}", 5 + index),
+        }
+    }
+
+    fn call(&mut self, function: &str, args: u16) {
+        let ret = self.unique_label("ret");
+        // Save the return address and the frame of the caller
+        self.emit(&[&format!("@{ret}"), "D=A"]);
+        self.push_d();
+        for pointer in ["@LCL", "@ARG", "@THIS", "@THAT"] {
+            self.emit(&[pointer, "D=M"]);
+            self.push_d();
+        }
+        // ARG = SP - 5 - args, LCL = SP
+        self.emit(&[
+            "@SP",
+            "D=M",
+            &format!("@{}", args + 5),
+            "D=D-A",
+            "@ARG",
+            "M=D",
+        ]);
+        self.emit(&["@SP", "D=M", "@LCL", "M=D"]);
+        self.emit(&[&format!("@{function}"), "0;JMP"]);
+        self.res.push(format!("({ret})"));
+    }
+
+    fn ret(&mut self) {
+        // R13 = frame, R14 = return address
+        self.emit(&[
+            "@LCL", "D=M", "@R13", "M=D", "@5", "A=D-A", "D=M", "@R14", "M=D",
+        ]);
+        // Return value to the caller and restore its stack pointer
+        self.emit(&[
+            "@SP", "AM=M-1", "D=M", "@ARG", "A=M", "M=D", "@ARG", "D=M+1", "@SP", "M=D",
+        ]);
+        for pointer in ["@THAT", "@THIS", "@ARG", "@LCL"] {
+            self.emit(&["@R13", "AM=M-1", "D=M", pointer, "M=D"]);
+        }
+        self.emit(&["@R14", "A=M", "0;JMP"]);
+    }
+
+    fn push_d(&mut self) {
+        self.emit(&["@SP", "AM=M+1", "A=A-1", "M=D"]);
+    }
+
+    fn unique_label(&mut self, kind: &str) -> String {
+        self.label_count += 1;
+        format!("{}${kind}.{}", self.function, self.label_count)
+    }
+
+    fn emit(&mut self, instructions: &[&str]) {
+        self.res.extend(instructions.iter().map(|x| x.to_string()));
+    }
+}
+
+fn base_of(segment: Segment) -> &'static str {
+    match segment {
+        Segment::Local => "@LCL",
+        Segment::Argument => "@ARG",
+        Segment::This => "@THIS",
+        _ => "@THAT",
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: jack_compiler [options] <file.jack | file.asm | directory>
+       jack_compiler run [options] <file.jack | directory>
+
+Options:
+  --strict         report type mismatches as errors instead of warnings
+  --precedence     give `*` and `/` priority over `+` and `-`, then comparisons,
+                   then `&` and `|`, instead of evaluating from left to right
+  --escapes        read \\n, \\\" and \\\\ in strings as escape sequences
+  -O               optimize the generated VM code
+  --remove-unused  leave out the subroutines that the program never calls
+                   when translating or running a directory
+  --emit <kind>    output to produce: vm (default), asm or hack, or the
+                   nand2tetris tokenizer and parser files: tokens-xml (xxxT.xml)
+                   or parse-xml (xxx.xml), or the syntax tree as ast-json (xxx.json)
+  --max-steps <n>  number of VM commands after which `run` stops the program
+  --keys <file>    key events for `run`, as `<step> <key>` lines
+  --snapshot <step>:<file>
+                   save the screen during `run` after the given number of steps
+                   (or `end`) as a .pbm or .png image, can be repeated; text
+                   printed with Output goes to stdout and is not drawn";
+
+/** Steps before a program run is considered stuck */
+const DEFAULT_MAX_STEPS: usize = 100_000_000;
+
+/** What the compiler writes out */
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Emit {
+    /**a .vm file per class*/
+    Vm,
+    /**a single .asm file for the file or directory*/
+    Asm,
+    /**a single .hack file with the binary code of the file or directory*/
+    Hack,
+    /**a xxxT.xml file per class with its tokens*/
+    TokensXml,
+    /**a xxx.xml file per class with its parse tree*/
+    ParseXml,
+    /**a xxx.json file per class with its syntax tree and the spans*/
+    AstJson,
+}
+
+impl Emit {
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "vm" => Ok(Emit::Vm),
+            "asm" => Ok(Emit::Asm),
+            "hack" => Ok(Emit::Hack),
+            "tokens-xml" => Ok(Emit::TokensXml),
+            "parse-xml" => Ok(Emit::ParseXml),
+            "ast-json" => Ok(Emit::AstJson),
+            _ => Err(format!("unknown output kind: {s}")),
+        }
+    }
+}
+
+pub struct Options {
+    pub path: PathBuf,
+    pub strict: bool,
+    /**parse expressions with the conventional operator precedence*/
+    pub precedence: bool,
+    /**escape sequences in string literals*/
+    pub escapes: bool,
+    pub emit: Emit,
+    /**shrink the generated VM code*/
+    pub optimize: bool,
+    /**remove the functions unreachable from the entry point of a whole program*/
+    pub remove_unused: bool,
+    /**run the program instead of writing the output*/
+    pub run: bool,
+    pub max_steps: usize,
+    /**file with the key events of the run*/
+    pub keys: Option<PathBuf>,
+    /**screen images to save, None for when the program stops*/
+    pub snapshots: Vec<(Option<usize>, PathBuf)>,
+}
+
+impl Options {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut path = None;
+        let mut strict = false;
+        let mut precedence = false;
+        let mut escapes = false;
+        let mut emit = Emit::Vm;
+        let mut optimize = false;
+        let mut remove_unused = false;
+        let mut run = false;
+        let mut max_steps = DEFAULT_MAX_STEPS;
+        let mut keys = None;
+        let mut snapshots = vec![];
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "run" if path.is_none() && !run => run = true,
+                "--max-steps" => {
+                    let value = args.next().ok_or("--max-steps needs a value")?;
+                    max_steps = value
+                        .parse()
+                        .map_err(|_| format!("invalid number of steps: {value}"))?;
+                }
+                "--keys" => keys = Some(PathBuf::from(args.next().ok_or("--keys needs a file")?)),
+                "--snapshot" => {
+                    let value = args.next().ok_or("--snapshot needs a value")?;
+                    snapshots.push(parse_snapshot(&value)?);
+                }
+                "--strict" => strict = true,
+                "--precedence" => precedence = true,
+                "--escapes" => escapes = true,
+                "-O" => optimize = true,
+                "--remove-unused" => remove_unused = true,
+                "--emit" => emit = Emit::from_str(&args.next().ok_or("--emit needs a value")?)?,
+                flag if flag.starts_with("--emit=") => emit = Emit::from_str(&flag[7..])?,
+                flag if flag.starts_with('-') => Err(format!("unknown option: {flag}"))?,
+                _ if path.is_some() => Err(format!("unexpected argument: {arg}"))?,
+                _ => path = Some(PathBuf::from(arg)),
+            }
+        }
+        // The course files follow the source text, there is no grouping or decoding to show
+        if matches!(emit, Emit::TokensXml | Emit::ParseXml) {
+            if precedence {
+                Err("--precedence cannot be used with the XML outputs, they show the expressions as written")?
+            }
+            if escapes {
+                Err("--escapes cannot be used with the XML outputs, they show the strings as written")?
+            }
+        }
+        Ok(Self {
+            path: path.ok_or("no input file or directory given")?,
+            strict,
+            precedence,
+            escapes,
+            emit,
+            optimize,
+            remove_unused,
+            run,
+            max_steps,
+            keys,
+            snapshots,
+        })
+    }
+}
+
+// <step>:<file> or end:<file>
+fn parse_snapshot(value: &str) -> Result<(Option<usize>, PathBuf), String> {
+    let invalid = || format!("invalid snapshot: {value}, expected <step>:<file.pbm|file.png>");
+    let (step, path) = value.split_once(':').ok_or_else(invalid)?;
+    let step = match step {
+        "end" => None,
+        _ => Some(step.parse().map_err(|_| invalid())?),
+    };
+    let path = PathBuf::from(path);
+    if !path.extension().is_some_and(|x| x == "pbm" || x == "png") {
+        Err(invalid())?
+    }
+    Ok((step, path))
+}
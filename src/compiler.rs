@@ -1,10 +1,16 @@
+use crate::analysis::constant::{fold, value_of, value_of_term};
+use crate::analysis::flow;
+use crate::diagnostic::{Code, Diagnostic};
 use crate::grammar::expression::{Expression, KeywordConstant, Op, SubroutineCall, Term, UnaryOp};
 use crate::grammar::statement::{Statement, Statements};
 use crate::grammar::structure::{
     Class, ClassName, ClassVarType, SubroutineDec, SubroutineName, SubroutineType, Type,
 };
 use crate::grammar::terminal::Identifier;
-use crate::symbol_table::{Kind, SymbolTable};
+use crate::span::Span;
+use crate::symbol_table::{similar_name, Kind, SymbolTable};
+use crate::vm::{push_constant, ArithmeticOp, Segment, VmCommand};
+use std::collections::HashMap;
 use std::ops::Deref;
 
 pub struct Compiler {
@@ -12,23 +18,32 @@ pub struct Compiler {
     class_name: ClassName,
     /**name of the currently compiling subroutine*/
     subroutine_name: SubroutineName,
+    /**kind of the currently compiling subroutine*/
+    subroutine_type: SubroutineType,
+    /**kinds of all the subroutines of the class, for calls without a receiver*/
+    subroutine_types: HashMap<String, SubroutineType>,
     label_count: usize,
     class_symbol_table: SymbolTable,
     subroutine_symbol_table: SymbolTable,
+    /**problems that do not stop the compilation*/
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 impl Compiler {
     pub fn new() -> Self {
         Self {
-            class_name: Identifier("".into()),
-            subroutine_name: Identifier("".into()),
+            class_name: Identifier("".into(), Span::default()),
+            subroutine_name: Identifier("".into(), Span::default()),
+            subroutine_type: SubroutineType::Function,
+            subroutine_types: HashMap::new(),
             label_count: 0,
             class_symbol_table: SymbolTable::new(),
             subroutine_symbol_table: SymbolTable::new(),
+            diagnostics: vec![],
         }
     }
 
-    pub fn compile_class(&mut self, class: Class) -> Result<Vec<String>, String> {
+    pub fn compile_class(&mut self, class: Class) -> Vec<VmCommand> {
         self.class_name = class.0;
 
         self.class_symbol_table.reset();
@@ -38,303 +53,338 @@ impl Compiler {
                 ClassVarType::Static => Kind::Static,
                 ClassVarType::Field => Kind::Field,
             };
-            var_dec.2.iter().for_each(|var| {
-                self.class_symbol_table
-                    .define(var.0.clone(), var_dec.1.clone(), kind);
-            });
+            for var in &var_dec.2 {
+                if let Err(e) = self.class_symbol_table.define(var, var_dec.1.clone(), kind) {
+                    self.diagnostics.push(e);
+                }
+            }
         }
+        self.subroutine_types = class.2.iter().map(|x| (x.2 .0.clone(), x.0)).collect();
         // Compile all the subroutines and append them into the result
         let mut res = vec![];
         for sub_routine_dec in class.2 {
+            // Void subroutines may end without a return statement
+            let falls_through = !flow::terminates(&sub_routine_dec.4 .1);
             let mut vm_commands = match sub_routine_dec.0 {
-                SubroutineType::Constructor => self.compile_constructor(sub_routine_dec)?,
-                SubroutineType::Function => self.compile_function(sub_routine_dec)?,
-                SubroutineType::Method => self.compile_method(sub_routine_dec)?,
+                SubroutineType::Constructor => self.compile_constructor(sub_routine_dec),
+                SubroutineType::Function => self.compile_function(sub_routine_dec),
+                SubroutineType::Method => self.compile_method(sub_routine_dec),
             };
+            if falls_through {
+                vm_commands.push(VmCommand::Push(Segment::Constant, 0));
+                vm_commands.push(VmCommand::Return);
+            }
             res.append(&mut vm_commands)
         }
-        Ok(res)
+        res
     }
 
-    fn compile_constructor(
-        &mut self,
-        subroutine_dec: SubroutineDec,
-    ) -> Result<Vec<String>, String> {
-        let statements = self.create_subroutine_symbol_table(subroutine_dec)?;
+    fn compile_constructor(&mut self, subroutine_dec: SubroutineDec) -> Vec<VmCommand> {
+        let statements = self.create_subroutine_symbol_table(subroutine_dec);
         // Write the initial code for constructor
         let mut res = vec![
-            format!(
-                "function {}.{} {}",
-                self.class_name.0,
-                self.subroutine_name.0,
-                self.subroutine_symbol_table.var_count(Kind::Var)
-            ),
-            format!(
-                "push constant {}",
-                self.class_symbol_table.var_count(Kind::Field)
+            self.function_command(),
+            // Memory.alloc needs a positive size even for classes without fields
+            VmCommand::Push(
+                Segment::Constant,
+                self.class_symbol_table.var_count(Kind::Field).max(1) as u16,
             ),
-            "call Memory.alloc 1".into(),
-            "pop pointer 0".into(),
+            VmCommand::Call("Memory.alloc".into(), 1),
+            VmCommand::Pop(Segment::Pointer, 0),
         ];
         // Compile all the statements
-        res.append(
-            &mut self
-                .compile_statements(statements)?
-                .into_iter()
-                .map(|x| format!("  {x}"))
-                .collect(),
-        );
-        Ok(res)
+        res.append(&mut self.compile_statements(statements));
+        res
     }
 
-    fn compile_method(&mut self, subroutine_dec: SubroutineDec) -> Result<Vec<String>, String> {
-        let statements = self.create_subroutine_symbol_table(subroutine_dec)?;
+    fn compile_method(&mut self, subroutine_dec: SubroutineDec) -> Vec<VmCommand> {
+        let statements = self.create_subroutine_symbol_table(subroutine_dec);
         // Write the initial code for method call
         let mut res = vec![
-            format!(
-                "function {}.{} {}",
-                self.class_name.0,
-                self.subroutine_name.0,
-                self.subroutine_symbol_table.var_count(Kind::Var)
-            ),
-            "push argument 0".into(),
-            "pop pointer 0".into(),
+            self.function_command(),
+            VmCommand::Push(Segment::Argument, 0),
+            VmCommand::Pop(Segment::Pointer, 0),
         ];
         // Compile all the statements
-        res.append(
-            &mut self
-                .compile_statements(statements)?
-                .into_iter()
-                .map(|x| format!("  {x}"))
-                .collect(),
-        );
-        Ok(res)
+        res.append(&mut self.compile_statements(statements));
+        res
     }
 
-    fn compile_function(&mut self, subroutine_dec: SubroutineDec) -> Result<Vec<String>, String> {
-        let statements = self.create_subroutine_symbol_table(subroutine_dec)?;
+    fn compile_function(&mut self, subroutine_dec: SubroutineDec) -> Vec<VmCommand> {
+        let statements = self.create_subroutine_symbol_table(subroutine_dec);
         // Write the initial code for method call
-        let mut res = vec![format!(
-            "function {}.{} {}",
-            self.class_name.0,
-            self.subroutine_name.0,
-            self.subroutine_symbol_table.var_count(Kind::Var)
-        )];
+        let mut res = vec![self.function_command()];
         // Compile all the statements
-        res.append(
-            &mut self
-                .compile_statements(statements)?
-                .into_iter()
-                .map(|x| format!("  {x}"))
-                .collect(),
-        );
-        Ok(res)
+        res.append(&mut self.compile_statements(statements));
+        res
     }
 
-    fn compile_statements(&mut self, statements: Statements) -> Result<Vec<String>, String> {
+    fn compile_statements(&mut self, statements: Statements) -> Vec<VmCommand> {
         let mut res = vec![];
         // Compile all the statements and push them in the result
         for statement in statements {
-            res.append(&mut self.compile_statement(statement)?);
+            match self.compile_statement(statement) {
+                Ok(mut commands) => res.append(&mut commands),
+                // The code is thrown away but the next statements may have errors too
+                Err(e) => self.diagnostics.push(e),
+            }
         }
-        Ok(res)
+        res
     }
 
-    fn compile_statement(&mut self, statement: Statement) -> Result<Vec<String>, String> {
+    fn compile_statement(&mut self, statement: Statement) -> Result<Vec<VmCommand>, Diagnostic> {
         let mut res = vec![];
         match statement {
             Statement::LetStatement(s) => {
                 if let Some(idx) = s.1 {
                     // Array indexing done
-                    res.push(format!("push {}", self.mapping_of(&s.0 .0)?));
+                    res.push(self.push_variable(&s.0)?);
                     res.append(&mut self.compile_expression(&idx)?);
-                    res.push("add".into());
+                    res.push(VmCommand::Arithmetic(ArithmeticOp::Add));
                     res.append(&mut self.compile_expression(&s.2)?);
                     res.extend([
-                        "pop temp 0".into(),
-                        "pop pointer 1".into(),
-                        "push temp 0".into(),
-                        "pop that 0".into(),
+                        VmCommand::Pop(Segment::Temp, 0),
+                        VmCommand::Pop(Segment::Pointer, 1),
+                        VmCommand::Push(Segment::Temp, 0),
+                        VmCommand::Pop(Segment::That, 0),
                     ]);
                 } else {
                     res.append(&mut self.compile_expression(&s.2)?);
-                    res.push(format!("pop {}", self.mapping_of(&s.0 .0)?));
+                    let (segment, index) = self.mapping_of(&s.0)?;
+                    res.push(VmCommand::Pop(segment, index));
+                }
+            }
+            // Branches that can never run are still compiled to report their errors. Only `true`
+            // and `false` are folded: the condition goes through a bitwise `not`, so other
+            // constants are left to the comparison at run time
+            Statement::IfStatement(s) if matches!(value_of(&s.0), Some(0 | -1)) => {
+                let then = self.compile_statements(s.1);
+                let otherwise = match s.2 {
+                    Some(else_statements) => self.compile_statements(else_statements),
+                    None => vec![],
+                };
+                let taken = value_of(&s.0) == Some(-1);
+                res = if taken { then } else { otherwise };
+            }
+            Statement::WhileStatement(s) if matches!(value_of(&s.0), Some(0 | -1)) => {
+                let body = self.compile_statements(s.1);
+                if value_of(&s.0) == Some(-1) {
+                    // A loop that never ends needs no condition
+                    self.label_count += 1;
+                    let while_label = format!("WHILE_LABEL${}", self.label_count);
+                    res.push(VmCommand::Label(while_label.clone()));
+                    res.extend(body);
+                    res.push(VmCommand::Goto(while_label));
                 }
             }
             Statement::IfStatement(s) => {
                 self.label_count += 1;
                 // Compute the if condition
                 res.append(&mut self.compile_expression(&s.0)?);
-                res.push("not".into());
+                res.push(VmCommand::Arithmetic(ArithmeticOp::Not));
 
                 let if_label = format!("IF_LABEL${}", self.label_count);
                 let else_label = format!("ELSE_LABEL${}", self.label_count);
 
-                res.push(format!("if-goto {}", else_label));
+                res.push(VmCommand::IfGoto(else_label.clone()));
                 // Compile the statements in the if block
-                res.append(
-                    &mut self
-                        .compile_statements(s.1)?
-                        .into_iter()
-                        .map(|x| format!("  {x}"))
-                        .collect(),
-                );
-                res.push(format!("goto {}", if_label));
-                res.push(format!("label {}", else_label));
+                res.append(&mut self.compile_statements(s.1));
+                res.push(VmCommand::Goto(if_label.clone()));
+                res.push(VmCommand::Label(else_label));
                 // Compile the statements in the else block
                 if let Some(else_statements) = s.2 {
-                    res.append(
-                        &mut self
-                            .compile_statements(else_statements)?
-                            .into_iter()
-                            .map(|x| format!("  {x}"))
-                            .collect(),
-                    );
+                    res.append(&mut self.compile_statements(else_statements));
                 }
-                res.push(format!("label {}", if_label));
+                res.push(VmCommand::Label(if_label));
             }
             Statement::WhileStatement(s) => {
                 self.label_count += 1;
                 let while_label = format!("WHILE_LABEL${}", self.label_count);
                 let break_label = format!("BREAK_LABEL${}", self.label_count);
 
-                res.push(format!("label {}", while_label));
+                res.push(VmCommand::Label(while_label.clone()));
                 // Compute the while condition
                 res.append(&mut self.compile_expression(&s.0)?);
-                res.push("not".into());
-                res.push(format!("if-goto {}", break_label));
+                res.push(VmCommand::Arithmetic(ArithmeticOp::Not));
+                res.push(VmCommand::IfGoto(break_label.clone()));
                 // Compile the statements in the while block
-                res.append(
-                    &mut self
-                        .compile_statements(s.1)?
-                        .into_iter()
-                        .map(|x| format!("  {x}"))
-                        .collect(),
-                );
-                res.push(format!("goto {}", while_label));
-                res.push(format!("label {}", break_label));
+                res.append(&mut self.compile_statements(s.1));
+                res.push(VmCommand::Goto(while_label));
+                res.push(VmCommand::Label(break_label));
             }
             Statement::DoStatement(s) => {
                 res.append(&mut self.compile_subroutine_call(&s.0)?);
+                // Discard the return value
+                res.push(VmCommand::Pop(Segment::Temp, 0));
             }
             Statement::ReturnStatement(s) => {
-                if let Some(exp) = s.0 {
-                    res.append(&mut self.compile_expression(&exp)?);
-                    res.push("return".into())
+                // Void subroutines return 0 which the caller discards
+                match s.0 {
+                    Some(exp) => res.append(&mut self.compile_expression(&exp)?),
+                    None => res.push(VmCommand::Push(Segment::Constant, 0)),
                 }
+                res.push(VmCommand::Return)
             }
         }
         Ok(res)
     }
 
-    fn compile_subroutine_call(&self, sub_call: &SubroutineCall) -> Result<Vec<String>, String> {
+    fn compile_subroutine_call(
+        &self,
+        sub_call: &SubroutineCall,
+    ) -> Result<Vec<VmCommand>, Diagnostic> {
         let mut res = vec![];
         if let Some(name) = sub_call.0.as_ref() {
-            if let Ok(mapping) = self.mapping_of(&name.0) {
+            if let Ok((segment, index)) = self.mapping_of(name) {
                 // Method call
-                res.push(format!("push {}", mapping));
+                res.push(VmCommand::Push(segment, index));
                 for exp in sub_call.2.iter() {
                     res.append(&mut self.compile_expression(exp)?);
                 }
-                res.push(format!(
-                    "call {}.{} {}",
-                    self.class_name_of(&name.0)?,
-                    sub_call.1 .0,
-                    sub_call.2.len() + 1
+                res.push(VmCommand::Call(
+                    format!("{}.{}", self.class_name_of(name)?, sub_call.1 .0),
+                    sub_call.2.len() as u16 + 1,
                 ));
             } else {
                 // Function call to another class
                 for exp in sub_call.2.iter() {
                     res.append(&mut self.compile_expression(exp)?);
                 }
-                res.push(format!(
-                    "call {}.{} {}",
-                    name.0,
-                    sub_call.1 .0,
-                    sub_call.2.len()
+                res.push(VmCommand::Call(
+                    format!("{}.{}", name.0, sub_call.1 .0),
+                    sub_call.2.len() as u16,
                 ));
             }
         } else {
-            // Method call to this class static functions cannot be called like this
-            res.push("push pointer 0".into());
+            // Call to a subroutine of this class, only methods get this as the first argument
+            let name = &sub_call.1;
+            let is_method = match self.subroutine_types.get(&name.0) {
+                Some(SubroutineType::Method) => true,
+                Some(_) => false,
+                None => Err(Diagnostic::error(
+                    Code::UnknownSubroutine,
+                    name.1,
+                    format!(
+                        "class `{}` has no subroutine named `{}`",
+                        self.class_name.0, name.0
+                    ),
+                )
+                .with_label("not found in this class"))?,
+            };
+            if is_method {
+                if self.subroutine_type == SubroutineType::Function {
+                    Err(Diagnostic::error(
+                        Code::CallKindMismatch,
+                        sub_call.3,
+                        format!(
+                            "cannot call method `{}` from function `{}`",
+                            name.0, self.subroutine_name.0
+                        ),
+                    )
+                    .with_label("there is no `this` in a function")
+                    .with_secondary(self.subroutine_name.1, "this is a function")
+                    .with_note("call the method on an object instead"))?
+                }
+                res.push(VmCommand::Push(Segment::Pointer, 0));
+            }
             for exp in sub_call.2.iter() {
                 res.append(&mut self.compile_expression(exp)?);
             }
-            res.push(format!(
-                "call {}.{} {}",
-                self.class_name.0,
-                sub_call.1 .0,
-                sub_call.2.len() + 1
+            res.push(VmCommand::Call(
+                format!("{}.{}", self.class_name.0, name.0),
+                sub_call.2.len() as u16 + u16::from(is_method),
             ));
         }
         Ok(res)
     }
 
-    fn compile_expression(&self, expression: &Expression) -> Result<Vec<String>, String> {
+    fn compile_expression(&self, expression: &Expression) -> Result<Vec<VmCommand>, Diagnostic> {
         let mut res = vec![];
-        res.append(&mut self.compile_term(&expression.0)?);
+        // Value of the expression so far while it is made of constants only
+        let mut value = value_of_term(&expression.0);
+        if value.is_none() {
+            res.append(&mut self.compile_term(&expression.0)?);
+        }
         for op_term in &expression.1 {
-            res.append(&mut self.compile_term(&op_term.1)?);
-            res.push(
-                match op_term.0 {
-                    Op::Add => "add",
-                    Op::Sub => "sub",
-                    Op::Mul => "call Math.multiply 2",
-                    Op::Div => "call Math.divide 2",
-                    Op::And => "and",
-                    Op::Or => "or",
-                    Op::Lt => "lt",
-                    Op::Gt => "gt",
-                    Op::Eq => "eq",
+            let (op, term) = (op_term.0, &op_term.1);
+            let operand = value_of_term(term);
+            if let Some(folded) = value.zip(operand).and_then(|(x, y)| fold(op, x, y)) {
+                value = Some(folded);
+                continue;
+            }
+            if let Some(x) = value.take() {
+                // The operator only keeps the other operand, like `0 + x` or `4 * x`
+                if left_identity(op) == Some(x) || doublings(op, x).is_some() {
+                    res.append(&mut self.compile_term(term)?);
+                    res.append(&mut double(doublings(op, x).unwrap_or_default()));
+                    continue;
                 }
-                .into(),
-            );
+                res.append(&mut push_constant(x));
+            }
+            if operand.is_some_and(|y| right_identity(op) == Some(y)) {
+                continue;
+            }
+            if let Some(n) = operand.and_then(|y| doublings(op, y)) {
+                res.append(&mut double(n));
+                continue;
+            }
+            res.append(&mut self.compile_term(term)?);
+            res.push(op_command(op));
+        }
+        if let Some(x) = value {
+            res.append(&mut push_constant(x));
         }
         Ok(res)
     }
 
-    fn compile_term(&self, term: &Term) -> Result<Vec<String>, String> {
+    fn compile_term(&self, term: &Term) -> Result<Vec<VmCommand>, Diagnostic> {
+        if let Some(value) = value_of_term(term) {
+            return Ok(push_constant(value));
+        }
         let mut res = vec![];
         match term {
-            Term::IntegerConstant(c) => res.push(format!("push constant {c}")),
-            Term::StringConstant(s) => {
+            Term::IntegerConstant(c, _) => res.push(VmCommand::Push(Segment::Constant, *c)),
+            Term::StringConstant(s, _) => {
                 res.extend([
-                    format!("push constant {}", s.len()),
-                    "call String.new 1".into(),
+                    VmCommand::Push(Segment::Constant, s.chars().count() as u16),
+                    VmCommand::Call("String.new".into(), 1),
                 ]);
                 for c in s.chars() {
-                    res.push(format!("push constant {}", c as u8));
-                    res.push("call String.appendChar 2".into());
+                    res.push(VmCommand::Push(Segment::Constant, c as u16));
+                    res.push(VmCommand::Call("String.appendChar".into(), 2));
                 }
             }
-            Term::KeywordConstant(c) => {
+            Term::KeywordConstant(c, _) => {
                 match c {
-                    KeywordConstant::True => res.extend(["push constant 1".into(), "neg".into()]),
+                    KeywordConstant::True => res.extend([
+                        VmCommand::Push(Segment::Constant, 1),
+                        VmCommand::Arithmetic(ArithmeticOp::Neg),
+                    ]),
                     KeywordConstant::False | KeywordConstant::Null => {
-                        res.extend(["push constant 0".into()])
+                        res.push(VmCommand::Push(Segment::Constant, 0))
                     }
-                    KeywordConstant::This => res.extend(["push pointer 0".into()]),
+                    KeywordConstant::This => res.push(VmCommand::Push(Segment::Pointer, 0)),
                 };
             }
-            Term::VarName(var) => res.push(format!("push {}", self.mapping_of(&var.0)?)),
-            Term::VarNameIndex(var, index) => {
-                res.push(format!("push {}", self.mapping_of(&var.0)?));
+            Term::VarName(var) => res.push(self.push_variable(var)?),
+            Term::VarNameIndex(var, index, _) => {
+                res.push(self.push_variable(var)?);
                 // Push the index expression
                 res.append(&mut self.compile_expression(index.deref())?);
-                res.push("add".into());
+                res.push(VmCommand::Arithmetic(ArithmeticOp::Add));
                 // Now point to this computed address
-                res.push("pop pointer 1".into());
+                res.push(VmCommand::Pop(Segment::Pointer, 1));
                 // Push the value at that address
-                res.push("push that 0".into());
+                res.push(VmCommand::Push(Segment::That, 0));
             }
-            Term::BracketExpression(exp) => {
+            Term::BracketExpression(exp, _) => {
                 res.append(&mut self.compile_expression(exp.deref())?);
             }
-            Term::UnaryOpTerm(op, term) => {
+            Term::UnaryOpTerm(op, term, _) => {
                 res.append(&mut self.compile_term(term.deref())?);
                 match op {
-                    UnaryOp::Minus => res.push("neg".into()),
-                    UnaryOp::Not => res.push("not".into()),
+                    UnaryOp::Minus => res.push(VmCommand::Arithmetic(ArithmeticOp::Neg)),
+                    UnaryOp::Not => res.push(VmCommand::Arithmetic(ArithmeticOp::Not)),
                 };
             }
             Term::SubroutineCall(sub) => res.append(&mut self.compile_subroutine_call(sub)?),
@@ -342,60 +392,219 @@ impl Compiler {
         Ok(res)
     }
 
-    fn create_subroutine_symbol_table(
-        &mut self,
-        subroutine_dec: SubroutineDec,
-    ) -> Result<Statements, String> {
+    fn create_subroutine_symbol_table(&mut self, subroutine_dec: SubroutineDec) -> Statements {
         // Set the subroutine name for the current subroutine
         self.subroutine_name = subroutine_dec.2;
+        self.subroutine_type = subroutine_dec.0;
         // Reset the table to remove any previous local variables
         self.subroutine_symbol_table.reset();
         // Create this entry in the symbol table if the subroutine is a method
-        match subroutine_dec.0 {
-            SubroutineType::Method => {
-                self.subroutine_symbol_table.define(
-                    "this".into(),
-                    Type::ClassName(self.class_name.clone()),
-                    Kind::Arg,
-                );
-            }
-            _ => {}
+        if let SubroutineType::Method = subroutine_dec.0 {
+            let this = Identifier("this".into(), Span::default());
+            self.define_local(&this, Type::ClassName(self.class_name.clone()), Kind::Arg);
         }
         // Create the arguments in the symbol table
         for params in subroutine_dec.3 {
-            self.subroutine_symbol_table
-                .define(params.1 .0, params.0, Kind::Arg);
+            self.define_local(&params.1, params.0, Kind::Arg);
         }
         let subroutine_body = subroutine_dec.4;
         // Create the local variables in the symbol table
         for var_dec in subroutine_body.0 {
-            var_dec.1.into_iter().for_each(|var_name| {
-                self.subroutine_symbol_table
-                    .define(var_name.0, var_dec.0.clone(), Kind::Var);
-            });
+            for var_name in &var_dec.1 {
+                self.define_local(var_name, var_dec.0.clone(), Kind::Var);
+                // Fields and statics become unreachable by name in the subroutine
+                if let Some(span) = self.class_symbol_table.span_of(&var_name.0) {
+                    self.diagnostics.push(
+                        Diagnostic::warning(
+                            Code::ShadowedVariable,
+                            var_name.1,
+                            format!("local variable `{}` shadows a class variable", var_name.0),
+                        )
+                        .with_label("shadows the class variable")
+                        .with_secondary(span, "class variable declared here"),
+                    );
+                }
+            }
+        }
+
+        subroutine_body.1
+    }
+
+    fn define_local(&mut self, name: &Identifier, tp: Type, kind: Kind) {
+        if let Err(e) = self.subroutine_symbol_table.define(name, tp, kind) {
+            self.diagnostics.push(e);
         }
+    }
+
+    fn function_command(&self) -> VmCommand {
+        VmCommand::Function(
+            format!("{}.{}", self.class_name.0, self.subroutine_name.0),
+            self.subroutine_symbol_table.var_count(Kind::Var) as u16,
+        )
+    }
 
-        Ok(subroutine_body.1)
+    fn push_variable(&self, name: &Identifier) -> Result<VmCommand, Diagnostic> {
+        let (segment, index) = self.mapping_of(name)?;
+        Ok(VmCommand::Push(segment, index))
     }
 
-    fn mapping_of(&self, name: &String) -> Result<String, String> {
+    fn mapping_of(&self, name: &Identifier) -> Result<(Segment, u16), Diagnostic> {
         if let Ok(map) = self.subroutine_symbol_table.mapping_of(name) {
             return Ok(map);
         }
-        self.class_symbol_table.mapping_of(name)
+        self.class_symbol_table.mapping_of(name).map_err(|err| {
+            let names = self
+                .subroutine_symbol_table
+                .names()
+                .chain(self.class_symbol_table.names());
+            match similar_name(&name.0, names) {
+                Some(similar) => err.with_note(format!("did you mean `{similar}`?")),
+                None => err,
+            }
+        })
     }
 
-    fn class_name_of(&self, name: &String) -> Result<String, String> {
-        if let Some(tp) = self.subroutine_symbol_table.type_of(name) {
-            if let Type::ClassName(Identifier(v)) = tp {
-                return Ok(v);
-            }
-        }
-        if let Some(tp) = self.class_symbol_table.type_of(name) {
-            if let Type::ClassName(Identifier(v)) = tp {
-                return Ok(v);
+    fn class_name_of(&self, name: &Identifier) -> Result<String, Diagnostic> {
+        let tp = self
+            .subroutine_symbol_table
+            .type_of(&name.0)
+            .or_else(|| self.class_symbol_table.type_of(&name.0));
+        match tp {
+            Some(Type::ClassName(Identifier(v, _))) => Ok(v),
+            tp => {
+                let mut err = Diagnostic::error(
+                    Code::NotAnObject,
+                    name.1,
+                    format!("`{}` is not an object", name.0),
+                )
+                .with_label("methods can only be called on objects");
+                if let Some(tp) = tp {
+                    err = err.with_note(format!("`{}` has type `{tp}`", name.0));
+                }
+                Err(err)
             }
         }
-        Err("not a class")?
+    }
+}
+
+fn op_command(op: Op) -> VmCommand {
+    match op {
+        Op::Add => VmCommand::Arithmetic(ArithmeticOp::Add),
+        Op::Sub => VmCommand::Arithmetic(ArithmeticOp::Sub),
+        Op::Mul => VmCommand::Call("Math.multiply".into(), 2),
+        Op::Div => VmCommand::Call("Math.divide".into(), 2),
+        Op::And => VmCommand::Arithmetic(ArithmeticOp::And),
+        Op::Or => VmCommand::Arithmetic(ArithmeticOp::Or),
+        Op::Lt => VmCommand::Arithmetic(ArithmeticOp::Lt),
+        Op::Gt => VmCommand::Arithmetic(ArithmeticOp::Gt),
+        Op::Eq => VmCommand::Arithmetic(ArithmeticOp::Eq),
+    }
+}
+
+// Constant x for which `x op y` is y
+fn left_identity(op: Op) -> Option<i16> {
+    match op {
+        Op::Add | Op::Or => Some(0),
+        Op::Mul => Some(1),
+        Op::And => Some(-1),
+        _ => None,
+    }
+}
+
+// Constant y for which `x op y` is x
+fn right_identity(op: Op) -> Option<i16> {
+    match op {
+        Op::Sub => Some(0),
+        Op::Div => Some(1),
+        _ => left_identity(op),
+    }
+}
+
+// n when multiplying by the value is the same as doubling n times
+fn doublings(op: Op, value: i16) -> Option<u32> {
+    (op == Op::Mul && value > 1 && value.count_ones() == 1).then(|| value.trailing_zeros())
+}
+
+// Multiply the top of the stack by 2^n with additions, temp 1 holds the value being added
+fn double(n: u32) -> Vec<VmCommand> {
+    (0..n)
+        .flat_map(|_| {
+            [
+                VmCommand::Pop(Segment::Temp, 1),
+                VmCommand::Push(Segment::Temp, 1),
+                VmCommand::Push(Segment::Temp, 1),
+                VmCommand::Arithmetic(ArithmeticOp::Add),
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compiler;
+    use crate::diagnostic::{Code, Diagnostic};
+    use crate::parser::Parser;
+    use crate::vm::VmCommand;
+
+    fn compile(source: &str) -> (Vec<VmCommand>, Vec<Diagnostic>) {
+        let content = source.chars().collect::<Vec<_>>();
+        let class = Parser::new(&content, 0).parse().0.unwrap();
+        let mut compiler = Compiler::new();
+        let commands = compiler.compile_class(class);
+        (commands, compiler.diagnostics)
+    }
+
+    // The body of a function compiled from the given statements, as text
+    fn body(statements: &str) -> Vec<String> {
+        let source = format!("class Main {{ function void f() {{ var int x; {statements} }} }}");
+        let (commands, _) = compile(&source);
+        commands[1..].iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let (_, diagnostics) = compile(
+            "class Main {
+                function void main() {
+                    let x = 1;
+                    if (true) { do Output.printInt(y); }
+                    return;
+                }
+                function int f() { return z; }
+            }",
+        );
+        let undefined = diagnostics
+            .iter()
+            .filter(|x| x.code == Code::UndefinedVariable)
+            .count();
+        assert_eq!(undefined, 3);
+    }
+
+    #[test]
+    fn constant_conditions() {
+        assert_eq!(
+            body("if (true) { let x = 1; } return;")[..2],
+            ["push constant 1", "pop local 0"]
+        );
+        assert_eq!(
+            body("if (false) { let x = 1; } return;")[0],
+            "push constant 0"
+        );
+        assert_eq!(
+            body("while (false) { let x = 1; } return;")[0],
+            "push constant 0"
+        );
+        assert!(body("while (true) { let x = 1; }")[0].starts_with("label WHILE_LABEL"));
+        // Only -1 is true after the `not` of the condition, 1 takes the else branch at run time
+        let code = body("if (1) { let x = 1; } return;");
+        assert_eq!(
+            code[..3],
+            ["push constant 1", "not", "if-goto ELSE_LABEL$1"]
+        );
+        let code = body("while (2) { let x = 1; }");
+        assert_eq!(
+            code[1..4],
+            ["push constant 2", "not", "if-goto BREAK_LABEL$1"]
+        );
     }
 }
@@ -0,0 +1,271 @@
+use crate::source::SourceMap;
+use crate::span::Span;
+use std::fmt::{Display, Formatter};
+use std::ops::{Deref, DerefMut};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Code {
+    UnexpectedToken,
+    UnexpectedEof,
+    UndefinedVariable,
+    NotAnObject,
+    TypeMismatch,
+    ConditionNotBoolean,
+    ReturnValueInVoid,
+    MissingReturnValue,
+    UnknownClass,
+    UnknownSubroutine,
+    ArgumentCount,
+    CallKindMismatch,
+    DuplicateDefinition,
+    ShadowedVariable,
+    UnusedVariable,
+    NeverRead,
+    MissingReturn,
+    UnreachableStatement,
+    InvalidConstructor,
+    ThisInFunction,
+    InvalidVmCommand,
+    InvalidInstruction,
+    InvalidKeyEvent,
+    MixedPrecedence,
+    IntegerOutOfRange,
+    ConstantOverflow,
+    UnterminatedString,
+    InvalidEscape,
+    IllegalCharacter,
+    NonAsciiCharacter,
+    UnterminatedComment,
+    ProgramTooLarge,
+}
+
+impl Code {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::UnexpectedToken => "E0001",
+            Code::UnexpectedEof => "E0002",
+            Code::UndefinedVariable => "E0003",
+            Code::NotAnObject => "E0004",
+            Code::TypeMismatch => "E0005",
+            Code::ConditionNotBoolean => "E0006",
+            Code::ReturnValueInVoid => "E0007",
+            Code::MissingReturnValue => "E0008",
+            Code::UnknownClass => "E0009",
+            Code::UnknownSubroutine => "E0010",
+            Code::ArgumentCount => "E0011",
+            Code::CallKindMismatch => "E0012",
+            Code::DuplicateDefinition => "E0013",
+            Code::ShadowedVariable => "E0014",
+            Code::UnusedVariable => "E0015",
+            Code::NeverRead => "E0016",
+            Code::MissingReturn => "E0017",
+            Code::UnreachableStatement => "E0018",
+            Code::InvalidConstructor => "E0019",
+            Code::ThisInFunction => "E0020",
+            Code::InvalidVmCommand => "E0021",
+            Code::InvalidInstruction => "E0022",
+            Code::InvalidKeyEvent => "E0023",
+            Code::MixedPrecedence => "E0024",
+            Code::IntegerOutOfRange => "E0025",
+            Code::ConstantOverflow => "E0026",
+            Code::UnterminatedString => "E0027",
+            Code::InvalidEscape => "E0028",
+            Code::IllegalCharacter => "E0029",
+            Code::NonAsciiCharacter => "E0030",
+            Code::UnterminatedComment => "E0031",
+            Code::ProgramTooLarge => "E0032",
+        }
+    }
+}
+
+impl Display for Code {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/** A report about the source, boxed so that results carrying one stay small */
+#[derive(Debug, Clone)]
+pub struct Diagnostic(Box<Details>);
+
+#[derive(Debug, Clone)]
+pub struct Details {
+    pub code: Code,
+    pub severity: Severity,
+    pub message: String,
+    /**the location the diagnostic is about*/
+    pub span: Span,
+    /**text printed under the primary span*/
+    pub label: Option<String>,
+    /**other related locations*/
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: Code, span: Span, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, span, message)
+    }
+
+    pub fn warning(code: Code, span: Span, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, span, message)
+    }
+
+    fn new(severity: Severity, code: Code, span: Span, message: impl Into<String>) -> Self {
+        Self(Box::new(Details {
+            code,
+            severity,
+            message: message.into(),
+            span,
+            label: None,
+            labels: vec![],
+            notes: vec![],
+        }))
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /** Render the diagnostic the way rustc does, with the source line and a caret underline */
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut res = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+
+        // Labels in line order, the file of the primary label first
+        let mut labels = vec![(self.span, self.label.clone().unwrap_or_default(), '^')];
+        labels.extend(self.labels.iter().map(|x| (x.span, x.message.clone(), '-')));
+        labels.sort_by_key(|x| (x.0.file != self.span.file, x.0.file, x.0.start.line));
+
+        let width = labels
+            .iter()
+            .map(|x| x.0.start.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let pad = " ".repeat(width);
+
+        let mut current_file = None;
+        let mut current_line = None;
+        for (span, message, marker) in labels {
+            // Diagnostics about generated code have no location
+            if span == Span::default() {
+                continue;
+            }
+            let Some(source) = sources.get(span.file) else {
+                continue;
+            };
+            if current_file != Some(span.file) {
+                // Other files are introduced the way rustc does it
+                let (arrow, start) = if current_file.is_none() {
+                    ("-->", self.span.start)
+                } else {
+                    (":::", span.start)
+                };
+                res += &format!(
+                    "{pad}{arrow} {}:{}:{}\n{pad} |\n",
+                    source.path.display(),
+                    start.line,
+                    start.column
+                );
+                current_file = Some(span.file);
+            }
+            let line = source.line(span.start.line).unwrap_or_default();
+            let chars = line.chars().collect::<Vec<_>>();
+            // Columns after expanding the tabs
+            let display_col = |col: usize| -> usize {
+                chars
+                    .iter()
+                    .take(col.saturating_sub(1))
+                    .map(|&c| if c == '\t' { 4 } else { 1 })
+                    .sum()
+            };
+            let start = display_col(span.start.column);
+            let end = if span.end.line == span.start.line {
+                display_col(span.end.column)
+            } else {
+                display_col(chars.len() + 1)
+            };
+            let underline = marker.to_string().repeat(end.saturating_sub(start).max(1));
+            // Labels on the same line share the source line
+            if current_line != Some((span.file, span.start.line)) {
+                res += &format!(
+                    "{:>width$} | {}\n",
+                    span.start.line,
+                    line.replace('\t', "    ")
+                );
+                current_line = Some((span.file, span.start.line));
+            }
+            res += format!("{pad} | {}{underline} {message}", " ".repeat(start)).trim_end();
+            res += "\n";
+        }
+        if !self.notes.is_empty() {
+            res += &format!("{pad} |\n");
+        }
+        for note in &self.notes {
+            res += &format!("{pad} = note: {note}\n");
+        }
+        res
+    }
+}
+
+impl Deref for Diagnostic {
+    type Target = Details;
+
+    fn deref(&self) -> &Details {
+        &self.0
+    }
+}
+
+impl DerefMut for Diagnostic {
+    fn deref_mut(&mut self) -> &mut Details {
+        &mut self.0
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}[{}]: {} at {}",
+            self.severity, self.code, self.message, self.span
+        )
+    }
+}
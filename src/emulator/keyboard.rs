@@ -0,0 +1,86 @@
+use crate::backend::source_lines;
+use crate::diagnostic::{Code, Diagnostic};
+use crate::source::SourceFile;
+use crate::span::FileId;
+
+/** A change of the key held down, at the given step of the emulator */
+#[derive(Debug, Copy, Clone)]
+pub struct KeyEvent {
+    pub step: usize,
+    /**Jack character code, 0 when no key is pressed*/
+    pub key: i16,
+}
+
+/** Names of the keys that are not printable characters */
+const KEY_NAMES: &[(&str, i16)] = &[
+    ("none", 0),
+    ("space", 32),
+    ("enter", 128),
+    ("backspace", 129),
+    ("left", 130),
+    ("up", 131),
+    ("right", 132),
+    ("down", 133),
+    ("home", 134),
+    ("end", 135),
+    ("pageup", 136),
+    ("pagedown", 137),
+    ("insert", 138),
+    ("delete", 139),
+    ("esc", 140),
+];
+
+/** Read a key script, made of `<step> <key>` lines sorted by step, with `//` comments */
+pub fn parse_key_script(source: &SourceFile, file: FileId) -> Result<Vec<KeyEvent>, Diagnostic> {
+    let mut res: Vec<KeyEvent> = vec![];
+    for line in source_lines(source, file) {
+        let invalid = |message: String, label: &str| {
+            Diagnostic::error(Code::InvalidKeyEvent, line.span, message).with_label(label)
+        };
+        let Some((step, key)) = line.text.split_once(char::is_whitespace) else {
+            Err(invalid(
+                format!("expected `<step> <key>`, found `{}`", line.text),
+                "invalid key event",
+            ))?
+        };
+        let step = step.parse::<usize>().map_err(|_| {
+            invalid(
+                format!("`{step}` is not a step number"),
+                "expected a number",
+            )
+        })?;
+        if res.last().is_some_and(|x| x.step > step) {
+            Err(invalid(
+                "key events must be sorted by step".into(),
+                "comes before the previous event",
+            ))?
+        }
+        let key = key_code(key.trim()).ok_or_else(|| {
+            invalid(format!("unknown key `{}`", key.trim()), "unknown key")
+                .with_note("use a single character, a key code or a key name such as `left`")
+        })?;
+        res.push(KeyEvent { step, key });
+    }
+    Ok(res)
+}
+
+// A single character, a number or the name of a special key
+fn key_code(key: &str) -> Option<i16> {
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        // Letter keys give the upper case code like the Hack keyboard
+        if c.is_ascii_graphic() {
+            return Some(c.to_ascii_uppercase() as i16);
+        }
+    }
+    if let Ok(code) = key.parse::<i16>() {
+        return (0..=152).contains(&code).then_some(code);
+    }
+    if let Some(n) = key.strip_prefix('f').and_then(|x| x.parse::<i16>().ok()) {
+        return (1..=12).contains(&n).then_some(140 + n);
+    }
+    KEY_NAMES
+        .iter()
+        .find(|x| x.0 == key.to_lowercase())
+        .map(|x| x.1)
+}
@@ -0,0 +1,443 @@
+use crate::emulator::keyboard::KeyEvent;
+use crate::vm::{ArithmeticOp, Segment, VmCommand};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+pub mod keyboard;
+mod os;
+pub mod screen;
+
+pub const RAM_SIZE: usize = 32768;
+const STACK_START: usize = 256;
+const STATIC_START: usize = 16;
+pub const HEAP_START: usize = 2048;
+pub const SCREEN: usize = 16384;
+pub const KEYBOARD: usize = 24576;
+
+/** A VM command with its labels and static variables resolved */
+#[derive(Debug, Clone)]
+enum Instruction {
+    /**static variables are replaced by their address*/
+    Push(Segment, usize),
+    Pop(Segment, usize),
+    Arithmetic(ArithmeticOp),
+    Goto(usize),
+    IfGoto(usize),
+    Function(usize),
+    Call(String, usize),
+    Return,
+}
+
+struct Frame {
+    function: String,
+    return_pc: usize,
+}
+
+/** Runs VM programs with native implementations of the Jack OS */
+pub struct Emulator<'a> {
+    pub ram: Vec<i16>,
+    program: Vec<Instruction>,
+    /**entry point and name of every function*/
+    functions: HashMap<String, usize>,
+    pc: usize,
+    call_stack: Vec<Frame>,
+    /**number of VM commands executed so far*/
+    pub steps: usize,
+    halted: bool,
+    heap: os::Heap,
+    /**color used by the Screen functions, true for black*/
+    color: bool,
+    /**key events that have not happened yet, the next one last*/
+    key_events: Vec<KeyEvent>,
+    /**screen images to save at a given step, or when the program stops for None*/
+    snapshots: Vec<(Option<usize>, PathBuf)>,
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+}
+
+impl<'a> Emulator<'a> {
+    /** Load the VM units of a program, each unit has its own static variables */
+    pub fn load(
+        units: &[(String, Vec<VmCommand>)],
+        input: &'a mut dyn BufRead,
+        output: &'a mut dyn Write,
+    ) -> Result<Self, String> {
+        let mut program = vec![];
+        let mut functions = HashMap::new();
+        let mut statics = STATIC_START;
+        for (unit, commands) in units {
+            // Static variables of the unit are placed after the ones of the previous units
+            let mut unit_statics = HashMap::new();
+            // Labels are local to a function and may be used before they are declared
+            let mut labels = HashMap::new();
+            let mut jumps = vec![];
+            let mut function = String::new();
+            for command in commands {
+                let instruction = match command {
+                    VmCommand::Push(segment, index) | VmCommand::Pop(segment, index) => {
+                        let mut index = usize::from(*index);
+                        if *segment == Segment::Static {
+                            let next = statics + unit_statics.len();
+                            index = *unit_statics.entry(index).or_insert(next);
+                            if index >= STACK_START {
+                                Err(format!("too many static variables in `{unit}`"))?
+                            }
+                        }
+                        if matches!(command, VmCommand::Push(..)) {
+                            Instruction::Push(*segment, index)
+                        } else {
+                            Instruction::Pop(*segment, index)
+                        }
+                    }
+                    VmCommand::Arithmetic(op) => Instruction::Arithmetic(*op),
+                    VmCommand::Label(label) => {
+                        labels.insert(format!("{function}${label}"), program.len());
+                        continue;
+                    }
+                    VmCommand::Goto(label) | VmCommand::IfGoto(label) => {
+                        jumps.push((program.len(), format!("{function}${label}")));
+                        if matches!(command, VmCommand::Goto(_)) {
+                            Instruction::Goto(0)
+                        } else {
+                            Instruction::IfGoto(0)
+                        }
+                    }
+                    VmCommand::Function(name, locals) => {
+                        function = name.clone();
+                        functions.insert(function.clone(), program.len());
+                        Instruction::Function(usize::from(*locals))
+                    }
+                    VmCommand::Call(name, args) => {
+                        Instruction::Call(name.clone(), usize::from(*args))
+                    }
+                    VmCommand::Return => Instruction::Return,
+                };
+                program.push(instruction);
+            }
+            for (pc, label) in jumps {
+                let target = *labels
+                    .get(&label)
+                    .ok_or_else(|| format!("label `{label}` is not declared"))?;
+                program[pc] = match program[pc] {
+                    Instruction::Goto(_) => Instruction::Goto(target),
+                    _ => Instruction::IfGoto(target),
+                };
+            }
+            statics += unit_statics.len();
+        }
+
+        let mut ram = vec![0; RAM_SIZE];
+        ram[0] = STACK_START as i16;
+        Ok(Self {
+            ram,
+            program,
+            functions,
+            pc: 0,
+            call_stack: vec![],
+            steps: 0,
+            halted: false,
+            heap: os::Heap::new(),
+            color: true,
+            key_events: vec![],
+            snapshots: vec![],
+            input,
+            output,
+        })
+    }
+
+    /** Press and release keys at the given steps, events must be sorted by step */
+    pub fn set_key_events(&mut self, mut events: Vec<KeyEvent>) {
+        events.reverse();
+        self.key_events = events;
+    }
+
+    /** Save the screen to a .pbm or .png file at a step, or when the program stops for None */
+    pub fn add_snapshot(&mut self, step: Option<usize>, path: PathBuf) {
+        self.snapshots.push((step, path));
+    }
+
+    /** Run Sys.init, or Main.main when the program does not define it, for at most max_steps commands */
+    pub fn run(&mut self, max_steps: usize) -> Result<(), String> {
+        let res = self.run_steps(max_steps);
+        // The final screen is saved however the program stopped
+        for (_, path) in self.snapshots.iter().filter(|x| x.0.is_none()) {
+            self.save_snapshot(path)?;
+        }
+        res?;
+        self.output.flush().map_err(|e| e.to_string())
+    }
+
+    fn run_steps(&mut self, max_steps: usize) -> Result<(), String> {
+        let entry = if self.functions.contains_key("Sys.init") {
+            "Sys.init"
+        } else {
+            "Main.main"
+        };
+        // Returning to the bootstrap frame ends the program
+        self.pc = self.program.len();
+        self.call(entry, 0)?;
+        while !self.halted && self.pc < self.program.len() {
+            if self.steps >= max_steps {
+                Err(format!(
+                    "the program did not finish after {max_steps} steps"
+                ))?
+            }
+            while let Some(event) = self.key_events.last().filter(|x| x.step <= self.steps) {
+                self.ram[KEYBOARD] = event.key;
+                self.key_events.pop();
+            }
+            for (_, path) in self.snapshots.iter().filter(|x| x.0 == Some(self.steps)) {
+                self.save_snapshot(path)?;
+            }
+            self.steps += 1;
+            self.step().map_err(|e| self.trace(e))?;
+        }
+        Ok(())
+    }
+
+    fn save_snapshot(&self, path: &PathBuf) -> Result<(), String> {
+        let image = if path.extension().is_some_and(|x| x == "png") {
+            screen::to_png(self)
+        } else {
+            screen::to_pbm(self)
+        };
+        std::fs::write(path, image).map_err(|e| format!("cannot write {}: {e}", path.display()))
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        let instruction = self.program[self.pc].clone();
+        self.pc += 1;
+        match instruction {
+            Instruction::Push(segment, index) => {
+                let value = match segment {
+                    Segment::Constant => index as i16,
+                    _ => {
+                        let address = self.address_of(segment, index)?;
+                        self.ram[address]
+                    }
+                };
+                self.push(value)?;
+            }
+            Instruction::Pop(segment, index) => {
+                let address = self.address_of(segment, index)?;
+                self.ram[address] = self.pop()?;
+            }
+            Instruction::Arithmetic(op) => {
+                let y = self.pop()?;
+                let res = match op {
+                    ArithmeticOp::Neg => y.wrapping_neg(),
+                    ArithmeticOp::Not => !y,
+                    _ => {
+                        let x = self.pop()?;
+                        match op {
+                            ArithmeticOp::Add => x.wrapping_add(y),
+                            ArithmeticOp::Sub => x.wrapping_sub(y),
+                            ArithmeticOp::Eq => -i16::from(x == y),
+                            ArithmeticOp::Gt => -i16::from(x > y),
+                            ArithmeticOp::Lt => -i16::from(x < y),
+                            ArithmeticOp::And => x & y,
+                            _ => x | y,
+                        }
+                    }
+                };
+                self.push(res)?;
+            }
+            Instruction::Goto(target) => self.pc = target,
+            Instruction::IfGoto(target) => {
+                if self.pop()? != 0 {
+                    self.pc = target;
+                }
+            }
+            Instruction::Function(locals) => {
+                for _ in 0..locals {
+                    self.push(0)?;
+                }
+            }
+            Instruction::Call(function, args) => self.call(&function, args)?,
+            Instruction::Return => {
+                let Some(caller) = self.call_stack.pop() else {
+                    Err("return outside of a function")?
+                };
+                let frame = self.pointer(1)?;
+                let value = self.pop()?;
+                let arg = self.pointer(2)?;
+                // LCL, ARG, THIS and THAT of the caller are just below the frame
+                let saved = frame
+                    .checked_sub(4)
+                    .ok_or("the frame of the caller is outside of the memory")?;
+                self.ram[arg] = value;
+                self.ram[0] = (arg + 1) as i16;
+                for pointer in 1..=4 {
+                    self.ram[pointer] = self.ram[saved + pointer - 1];
+                }
+                self.pc = caller.return_pc;
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, function: &str, args: usize) -> Result<(), String> {
+        let Some(&entry) = self.functions.get(function) else {
+            // Functions of the program take precedence over the native OS
+            let sp = self.pointer(0)?;
+            if sp < STACK_START + args {
+                Err("stack underflow")?
+            }
+            let args = self.ram[sp - args..sp].to_vec();
+            self.ram[0] = (sp - args.len()) as i16;
+            let res = self
+                .call_native(function, &args)
+                .or_else(|| self.call_screen(function, &args))
+                .ok_or_else(|| format!("call to undefined function `{function}`"))?
+                .map_err(|e| format!("{e} in `{function}`"))?;
+            return self.push(res);
+        };
+        // The frame is saved in RAM the same way the generated assembly does it
+        let arg = self.pointer(0)?.checked_sub(args).ok_or_else(|| {
+            format!("`{function}` is called with more arguments than the stack has")
+        })?;
+        self.push(self.pc as i16)?;
+        for pointer in 1..=4 {
+            self.push(self.ram[pointer])?;
+        }
+        self.ram[2] = arg as i16;
+        self.ram[1] = self.ram[0];
+        self.call_stack.push(Frame {
+            function: function.to_string(),
+            return_pc: self.pc,
+        });
+        self.pc = entry;
+        Ok(())
+    }
+
+    fn address_of(&self, segment: Segment, index: usize) -> Result<usize, String> {
+        let base = |pointer: usize| self.ram[pointer] as u16 as usize;
+        let address = match segment {
+            Segment::Local => base(1) + index,
+            Segment::Argument => base(2) + index,
+            Segment::This => base(3) + index,
+            Segment::That => base(4) + index,
+            Segment::Pointer => 3 + index,
+            Segment::Temp => 5 + index,
+            Segment::Static | Segment::Constant => index,
+        };
+        check_address(address)
+    }
+
+    pub fn push(&mut self, value: i16) -> Result<(), String> {
+        let sp = self.pointer(0)?;
+        if sp >= HEAP_START {
+            Err("stack overflow")?
+        }
+        self.ram[sp] = value;
+        self.ram[0] += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<i16, String> {
+        let sp = self.pointer(0)?;
+        if sp <= STACK_START {
+            Err("stack underflow")?
+        }
+        self.ram[0] -= 1;
+        Ok(self.ram[sp - 1])
+    }
+
+    // Address held by SP, LCL or ARG, which a VM program can set to anything
+    fn pointer(&self, pointer: usize) -> Result<usize, String> {
+        let value = self.ram[pointer];
+        usize::try_from(value).map_err(|_| {
+            let name = ["SP", "LCL", "ARG"][pointer];
+            format!("{name} holds the negative address {value}")
+        })
+    }
+
+    // Add the functions being run to a runtime error
+    fn trace(&self, error: String) -> String {
+        let mut res = format!("runtime error: {error}");
+        for frame in self.call_stack.iter().rev() {
+            res += &format!("\n    in {}", frame.function);
+        }
+        res
+    }
+}
+
+fn check_address(address: usize) -> Result<usize, String> {
+    if address < RAM_SIZE {
+        Ok(address)
+    } else {
+        Err(format!("access to address {address} outside of the memory"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::keyboard::parse_key_script;
+    use super::Emulator;
+    use crate::compiler::Compiler;
+    use crate::parser::Parser;
+    use crate::source::SourceMap;
+    use crate::vm::VmCommand;
+    use std::fs::{read, read_to_string};
+
+    // Run a single unit and return the runtime error
+    fn error(text: &str) -> String {
+        let commands = text
+            .lines()
+            .map(|x| x.trim().parse::<VmCommand>().unwrap())
+            .collect();
+        let units = [("Main".to_string(), commands)];
+        let (mut input, mut output) = (&b""[..], vec![]);
+        let mut emulator = Emulator::load(&units, &mut input, &mut output).unwrap();
+        emulator.run(1000).unwrap_err()
+    }
+
+    #[test]
+    fn broken_pointers_are_runtime_errors() {
+        // THIS points to RAM[0], so `pop this n` overwrites SP, LCL or ARG
+        let set = |pointer: usize, value: &str| {
+            format!("push constant 0\npop pointer 0\n{value}\npop this {pointer}")
+        };
+        let main = |body: String| format!("function Main.main 0\n{body}\npush constant 0\nreturn");
+        let negative_sp = main(set(0, "push constant 1\nneg"));
+        assert!(error(&negative_sp).contains("SP holds the negative address -1"));
+        let small_frame = main(set(1, "push constant 2"));
+        assert!(error(&small_frame).contains("the frame of the caller is outside of the memory"));
+        let negative_arg = main(set(2, "push constant 5\nneg"));
+        assert!(error(&negative_arg).contains("ARG holds the negative address -5"));
+        let too_many_args = "function Main.main 0
+            call Main.f 300
+            return
+            function Main.f 0
+            push constant 0
+            return";
+        assert!(error(too_many_args).contains("more arguments than the stack has"));
+    }
+
+    #[test]
+    fn key_script_and_snapshot() {
+        let mut sources = SourceMap::new();
+        let mut add = |path: &str| {
+            let content = read_to_string(path).unwrap().chars().collect();
+            sources.add(path.into(), content)
+        };
+        let (main, keys) = (add("test/Keys/Main.jack"), add("test/Keys/keys.txt"));
+        let class = Parser::new(&sources.get(main).unwrap().content, main)
+            .parse()
+            .0
+            .unwrap();
+        let units = [("Main".to_string(), Compiler::new().compile_class(class))];
+        let key_events = parse_key_script(sources.get(keys).unwrap(), keys).unwrap();
+
+        let path = std::env::temp_dir().join(format!("jack_keys_{}.pbm", std::process::id()));
+        let (mut input, mut output) = (&b""[..], vec![]);
+        let mut emulator = Emulator::load(&units, &mut input, &mut output).unwrap();
+        emulator.set_key_events(key_events);
+        emulator.add_snapshot(None, path.clone());
+        emulator.run(100_000).unwrap();
+        let snapshot = read(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(snapshot, read("test/Keys/Main.pbm").unwrap());
+    }
+}
@@ -0,0 +1,263 @@
+use crate::emulator::{check_address, Emulator, HEAP_START, KEYBOARD, SCREEN};
+
+/** Character codes of the Jack character set that are not ASCII */
+const NEW_LINE: i16 = 128;
+const BACKSPACE: i16 = 129;
+const DOUBLE_QUOTE: i16 = 34;
+
+/** First fit allocator over the heap segment of the RAM */
+pub struct Heap {
+    /**start and size of the free blocks, sorted by address*/
+    free: Vec<(usize, usize)>,
+    /**size of the allocated blocks by address*/
+    allocated: Vec<(usize, usize)>,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            free: vec![(HEAP_START, SCREEN - HEAP_START)],
+            allocated: vec![],
+        }
+    }
+
+    fn alloc(&mut self, size: i16) -> Result<usize, String> {
+        if size <= 0 {
+            Err(format!("cannot allocate a block of size {size}"))?
+        }
+        let size = size as usize;
+        let i = self
+            .free
+            .iter()
+            .position(|x| x.1 >= size)
+            .ok_or("heap overflow")?;
+        let (start, free_size) = self.free[i];
+        if free_size == size {
+            self.free.remove(i);
+        } else {
+            self.free[i] = (start + size, free_size - size);
+        }
+        self.allocated.push((start, size));
+        Ok(start)
+    }
+
+    fn de_alloc(&mut self, address: i16) -> Result<(), String> {
+        let address = address as u16 as usize;
+        let i = self
+            .allocated
+            .iter()
+            .position(|x| x.0 == address)
+            .ok_or_else(|| format!("{address} is not an allocated block"))?;
+        let (start, size) = self.allocated.swap_remove(i);
+        let i = self.free.partition_point(|x| x.0 < start);
+        self.free.insert(i, (start, size));
+        // Merge with the neighbouring blocks
+        if i + 1 < self.free.len() && start + size == self.free[i + 1].0 {
+            self.free[i].1 += self.free.remove(i + 1).1;
+        }
+        if i > 0 && self.free[i - 1].0 + self.free[i - 1].1 == start {
+            self.free[i - 1].1 += self.free.remove(i).1;
+        }
+        Ok(())
+    }
+}
+
+impl Emulator<'_> {
+    /** Run an OS function natively, None if there is no such function */
+    pub(super) fn call_native(
+        &mut self,
+        function: &str,
+        args: &[i16],
+    ) -> Option<Result<i16, String>> {
+        let arg = |i: usize| args.get(i).copied().unwrap_or_default();
+        let res = match function {
+            "Math.init" | "Memory.init" | "Output.init" | "Keyboard.init" => Ok(0),
+            "Math.abs" => Ok(arg(0).wrapping_abs()),
+            "Math.multiply" => Ok(arg(0).wrapping_mul(arg(1))),
+            "Math.divide" if arg(1) == 0 => Err("division by zero".into()),
+            "Math.divide" => Ok(arg(0).wrapping_div(arg(1))),
+            "Math.min" => Ok(arg(0).min(arg(1))),
+            "Math.max" => Ok(arg(0).max(arg(1))),
+            "Math.sqrt" if arg(0) < 0 => Err("square root of a negative number".into()),
+            "Math.sqrt" => Ok((f64::from(arg(0))).sqrt() as i16),
+
+            "Memory.peek" => self.peek(arg(0)),
+            "Memory.poke" => self.poke(arg(0), arg(1)).map(|_| 0),
+            "Memory.alloc" | "Array.new" => self.heap.alloc(arg(0)).map(|x| x as i16),
+            "Memory.deAlloc" | "Array.dispose" | "String.dispose" => {
+                self.heap.de_alloc(arg(0)).map(|_| 0)
+            }
+
+            "String.new" => self.new_string(arg(0)),
+            "String.length" => self.peek(arg(0).wrapping_add(1)),
+            "String.charAt" => self.string_chars(arg(0)).and_then(|chars| {
+                let j = usize::try_from(arg(1)).ok().filter(|&x| x < chars.len());
+                j.map(|j| chars[j])
+                    .ok_or("string index out of bounds".into())
+            }),
+            "String.setCharAt" => self.string_chars(arg(0)).and_then(|chars| {
+                if !(0..chars.len() as i16).contains(&arg(1)) {
+                    Err("string index out of bounds")?
+                }
+                self.poke(arg(0).wrapping_add(2).wrapping_add(arg(1)), arg(2))
+                    .map(|_| 0)
+            }),
+            "String.appendChar" => self.append_char(arg(0), arg(1)).map(|_| arg(0)),
+            "String.eraseLastChar" => self.string_chars(arg(0)).and_then(|chars| {
+                if chars.is_empty() {
+                    Err("string is empty")?
+                }
+                self.poke(arg(0).wrapping_add(1), chars.len() as i16 - 1)
+                    .map(|_| 0)
+            }),
+            "String.intValue" => self.string_chars(arg(0)).map(|chars| int_value(&chars)),
+            "String.setInt" => self.set_int(arg(0), arg(1)).map(|_| 0),
+            "String.backSpace" => Ok(BACKSPACE),
+            "String.doubleQuote" => Ok(DOUBLE_QUOTE),
+            "String.newLine" => Ok(NEW_LINE),
+
+            "Output.moveCursor" => Ok(0),
+            "Output.printChar" => self.print(&[arg(0)]),
+            "Output.printString" => self.string_chars(arg(0)).and_then(|x| self.print(&x)),
+            "Output.printInt" => {
+                let text = arg(0)
+                    .to_string()
+                    .bytes()
+                    .map(i16::from)
+                    .collect::<Vec<_>>();
+                self.print(&text)
+            }
+            "Output.println" => self.print(&[NEW_LINE]),
+            "Output.backSpace" => self.print(&[BACKSPACE]),
+
+            "Keyboard.keyPressed" => Ok(self.ram[KEYBOARD]),
+            "Keyboard.readChar" => self.read_char(),
+            "Keyboard.readLine" => self
+                .string_chars(arg(0))
+                .and_then(|x| self.print(&x))
+                .and_then(|_| self.read_line())
+                .and_then(|x| self.string_of(&x)),
+            "Keyboard.readInt" => self
+                .string_chars(arg(0))
+                .and_then(|x| self.print(&x))
+                .and_then(|_| self.read_line())
+                .map(|x| int_value(&x)),
+
+            "Sys.init" => Err("`Sys.init` is the entry point and cannot be called".into()),
+            "Sys.halt" => {
+                self.halted = true;
+                Ok(0)
+            }
+            "Sys.error" => Err(format!("Sys.error called with error code {}", arg(0))),
+            "Sys.wait" => Ok(0),
+            _ => None?,
+        };
+        Some(res)
+    }
+
+    fn peek(&self, address: i16) -> Result<i16, String> {
+        Ok(self.ram[check_address(address as u16 as usize)?])
+    }
+
+    fn poke(&mut self, address: i16, value: i16) -> Result<(), String> {
+        self.ram[check_address(address as u16 as usize)?] = value;
+        Ok(())
+    }
+
+    // Strings are stored as the maximum length, the length and then the characters
+    fn new_string(&mut self, max_length: i16) -> Result<i16, String> {
+        if max_length < 0 {
+            Err(format!("cannot create a string of length {max_length}"))?
+        }
+        let address = self.heap.alloc(max_length.saturating_add(2))?;
+        self.ram[address] = max_length;
+        self.ram[address + 1] = 0;
+        Ok(address as i16)
+    }
+
+    fn string_chars(&self, string: i16) -> Result<Vec<i16>, String> {
+        let length = self.peek(string.wrapping_add(1))?;
+        (0..length)
+            .map(|i| self.peek(string.wrapping_add(2).wrapping_add(i)))
+            .collect()
+    }
+
+    fn append_char(&mut self, string: i16, c: i16) -> Result<(), String> {
+        let max_length = self.peek(string)?;
+        let length = self.peek(string.wrapping_add(1))?;
+        if length >= max_length {
+            Err("string is full")?
+        }
+        self.poke(string.wrapping_add(2).wrapping_add(length), c)?;
+        self.poke(string.wrapping_add(1), length + 1)
+    }
+
+    fn set_int(&mut self, string: i16, value: i16) -> Result<(), String> {
+        self.poke(string.wrapping_add(1), 0)?;
+        for c in value.to_string().bytes() {
+            self.append_char(string, i16::from(c))?;
+        }
+        Ok(())
+    }
+
+    fn string_of(&mut self, chars: &[i16]) -> Result<i16, String> {
+        let string = self.new_string(chars.len() as i16)?;
+        for &c in chars {
+            self.append_char(string, c)?;
+        }
+        Ok(string)
+    }
+
+    // Print Jack characters to the output, returns 0 as every OS function does
+    fn print(&mut self, chars: &[i16]) -> Result<i16, String> {
+        let text = chars
+            .iter()
+            .map(|&c| match c {
+                NEW_LINE => '\n',
+                BACKSPACE => '\x08',
+                c => u8::try_from(c).map_or('?', char::from),
+            })
+            .collect::<String>();
+        self.output
+            .write_all(text.as_bytes())
+            .map_err(|e| e.to_string())?;
+        Ok(0)
+    }
+
+    fn read_char(&mut self) -> Result<i16, String> {
+        self.output.flush().map_err(|e| e.to_string())?;
+        let mut byte = [0];
+        match self.input.read(&mut byte).map_err(|e| e.to_string())? {
+            0 => Err("no more input to read".into()),
+            _ if byte[0] == b'\n' => Ok(NEW_LINE),
+            _ => Ok(i16::from(byte[0])),
+        }
+    }
+
+    // A line of input without the line terminator
+    fn read_line(&mut self) -> Result<Vec<i16>, String> {
+        self.output.flush().map_err(|e| e.to_string())?;
+        let mut line = String::new();
+        self.input.read_line(&mut line).map_err(|e| e.to_string())?;
+        Ok(line
+            .trim_end_matches(['\n', '\r'])
+            .chars()
+            .map(|c| if c.is_ascii() { c as i16 } else { '?' as i16 })
+            .collect())
+    }
+}
+
+// Leading integer of a string, like the Jack OS String.intValue
+fn int_value(chars: &[i16]) -> i16 {
+    let (sign, digits) = match chars.first() {
+        Some(&c) if c == '-' as i16 => (-1, &chars[1..]),
+        _ => (1, chars),
+    };
+    digits
+        .iter()
+        .take_while(|&&c| (b'0' as i16..=b'9' as i16).contains(&c))
+        .fold(0i16, |acc, &c| {
+            acc.wrapping_mul(10).wrapping_add(c - b'0' as i16)
+        })
+        .wrapping_mul(sign)
+}
@@ -0,0 +1,212 @@
+use crate::emulator::{Emulator, SCREEN};
+
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+/** Number of 16 bit words in a row of the screen */
+const ROW_WORDS: usize = WIDTH / 16;
+
+impl Emulator<'_> {
+    /** The Screen OS class, None if there is no such function */
+    pub(super) fn call_screen(
+        &mut self,
+        function: &str,
+        args: &[i16],
+    ) -> Option<Result<i16, String>> {
+        let arg = |i: usize| i32::from(args.get(i).copied().unwrap_or_default());
+        let res = match function {
+            "Screen.init" => {
+                self.color = true;
+                Ok(())
+            }
+            "Screen.clearScreen" => {
+                self.ram[SCREEN..SCREEN + ROW_WORDS * HEIGHT].fill(0);
+                Ok(())
+            }
+            "Screen.setColor" => {
+                self.color = arg(0) != 0;
+                Ok(())
+            }
+            "Screen.drawPixel" => {
+                check_point(arg(0), arg(1)).map(|_| self.set_pixel(arg(0), arg(1)))
+            }
+            "Screen.drawLine" => check_point(arg(0), arg(1))
+                .and_then(|_| check_point(arg(2), arg(3)))
+                .map(|_| self.draw_line(arg(0), arg(1), arg(2), arg(3))),
+            "Screen.drawRectangle" => check_point(arg(0), arg(1))
+                .and_then(|_| check_point(arg(2), arg(3)))
+                .and_then(|_| {
+                    if arg(0) > arg(2) || arg(1) > arg(3) {
+                        Err("the first corner of a rectangle must be the top left one")?
+                    }
+                    for y in arg(1)..=arg(3) {
+                        self.draw_row(arg(0), arg(2), y);
+                    }
+                    Ok(())
+                }),
+            "Screen.drawCircle" => check_point(arg(0), arg(1)).and_then(|_| {
+                let (x, y, r) = (arg(0), arg(1), arg(2));
+                if !(0..=181).contains(&r) {
+                    Err(format!("illegal circle radius {r}"))?
+                }
+                // Filled with horizontal lines, the parts outside of the screen are clipped
+                for dy in -r..=r {
+                    let dx = f64::from(r * r - dy * dy).sqrt() as i32;
+                    self.draw_row(x - dx, x + dx, y + dy);
+                }
+                Ok(())
+            }),
+            _ => None?,
+        };
+        Some(res.map(|_| 0))
+    }
+
+    fn set_pixel(&mut self, x: i32, y: i32) {
+        if !(0..WIDTH as i32).contains(&x) || !(0..HEIGHT as i32).contains(&y) {
+            return;
+        }
+        // The least significant bit of a word is the leftmost pixel
+        let address = SCREEN + y as usize * ROW_WORDS + x as usize / 16;
+        let mask = 1i16 << (x % 16);
+        if self.color {
+            self.ram[address] |= mask;
+        } else {
+            self.ram[address] &= !mask;
+        }
+    }
+
+    fn draw_row(&mut self, x1: i32, x2: i32, y: i32) {
+        for x in x1..=x2 {
+            self.set_pixel(x, y);
+        }
+    }
+
+    // Bresenham's algorithm, including both ends
+    fn draw_line(&mut self, x1: i32, y1: i32, x2: i32, y2: i32) {
+        let (dx, dy) = ((x2 - x1).abs(), -(y2 - y1).abs());
+        let (sx, sy) = ((x2 - x1).signum(), (y2 - y1).signum());
+        let (mut x, mut y, mut error) = (x1, y1, dx + dy);
+        loop {
+            self.set_pixel(x, y);
+            if x == x2 && y == y2 {
+                break;
+            }
+            if 2 * error >= dy {
+                error += dy;
+                x += sx;
+            }
+            if 2 * error <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /** Whether the pixel is black */
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.ram[SCREEN + y * ROW_WORDS + x / 16] & (1 << (x % 16)) != 0
+    }
+}
+
+fn check_point(x: i32, y: i32) -> Result<(), String> {
+    if (0..WIDTH as i32).contains(&x) && (0..HEIGHT as i32).contains(&y) {
+        Ok(())
+    } else {
+        Err(format!("illegal screen coordinates ({x}, {y})"))
+    }
+}
+
+/** The screen as a binary PBM image, where 1 is black as on the Hack screen */
+pub fn to_pbm(emulator: &Emulator) -> Vec<u8> {
+    let mut res = format!("P4\n{WIDTH} {HEIGHT}\n").into_bytes();
+    res.extend(packed_rows(emulator, true).into_iter().flatten());
+    res
+}
+
+/** The screen as a 1 bit grayscale PNG image */
+pub fn to_png(emulator: &Emulator) -> Vec<u8> {
+    // Each row starts with the filter type, 0 for none
+    let mut raw = vec![];
+    for row in packed_rows(emulator, false) {
+        raw.push(0);
+        raw.extend(row);
+    }
+
+    let mut res = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut header = vec![];
+    header.extend((WIDTH as u32).to_be_bytes());
+    header.extend((HEIGHT as u32).to_be_bytes());
+    // Bit depth 1, grayscale, default compression, filter and no interlacing
+    header.extend([1, 0, 0, 0, 0]);
+    write_chunk(&mut res, b"IHDR", &header);
+    write_chunk(&mut res, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut res, b"IEND", &[]);
+    res
+}
+
+// Rows of pixels with 8 pixels per byte, the leftmost one in the most significant bit
+fn packed_rows(emulator: &Emulator, black_is_one: bool) -> Vec<Vec<u8>> {
+    (0..HEIGHT)
+        .map(|y| {
+            (0..WIDTH / 8)
+                .map(|byte| {
+                    (0..8).fold(0u8, |acc, bit| {
+                        let on = emulator.pixel(byte * 8 + bit, y) == black_is_one;
+                        acc << 1 | u8::from(on)
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn write_chunk(res: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    res.extend((data.len() as u32).to_be_bytes());
+    let start = res.len();
+    res.extend(kind);
+    res.extend(data);
+    let crc = crc32(&res[start..]);
+    res.extend(crc.to_be_bytes());
+}
+
+// A zlib stream made of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut res = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        res.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        res.push(u8::from(last));
+        res.extend(len.to_le_bytes());
+        res.extend((!len).to_le_bytes());
+        res.extend(block);
+    }
+    res.extend(adler32(data).to_be_bytes());
+    res
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
@@ -1,23 +1,42 @@
+use crate::diagnostic::Diagnostic;
 use crate::grammar::structure::{SubroutineName, VarName};
-use crate::grammar::terminal::{Identifier, Keyword};
+use crate::grammar::terminal::{Identifier, Keyword, Terminal, Token};
+use crate::span::Span;
+use std::fmt::{Display, Formatter};
 
 pub type ExpressionList = Vec<Expression>;
 
 #[derive(Debug)]
-pub struct Expression(pub Term, pub Vec<OpTerm>);
+pub struct Expression(pub Term, pub Vec<OpTerm>, pub Span);
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Term {
-    IntegerConstant(u16),
-    StringConstant(String),
-    KeywordConstant(KeywordConstant),
+    IntegerConstant(u16, Span),
+    StringConstant(String, Span),
+    KeywordConstant(KeywordConstant, Span),
     VarName(VarName),
-    VarNameIndex(VarName, Box<Expression>),
-    BracketExpression(Box<Expression>),
-    UnaryOpTerm(UnaryOp, Box<Term>),
+    VarNameIndex(VarName, Box<Expression>, Span),
+    BracketExpression(Box<Expression>, Span),
+    UnaryOpTerm(UnaryOp, Box<Term>, Span),
     SubroutineCall(SubroutineCall),
 }
-#[derive(Debug)]
+
+impl Term {
+    pub fn span(&self) -> Span {
+        match self {
+            Term::IntegerConstant(_, span)
+            | Term::StringConstant(_, span)
+            | Term::KeywordConstant(_, span)
+            | Term::VarNameIndex(_, _, span)
+            | Term::BracketExpression(_, span)
+            | Term::UnaryOpTerm(_, _, span) => *span,
+            Term::VarName(var) => var.1,
+            Term::SubroutineCall(call) => call.3,
+        }
+    }
+}
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum KeywordConstant {
     True,
     False,
@@ -26,16 +45,14 @@ pub enum KeywordConstant {
 }
 
 impl KeywordConstant {
-    pub fn from_keyword(keyword: Keyword) -> Result<Self, String> {
+    pub fn from_keyword(keyword: Keyword, span: Span) -> Result<Self, Diagnostic> {
         use KeywordConstant::*;
         let res = match keyword {
             Keyword::True => True,
             Keyword::False => False,
             Keyword::Null => Null,
             Keyword::This => This,
-            e => Err(format!(
-                "expected true|false|null|this expected found: {e:?}"
-            ))?,
+            e => Err(Token(Terminal::Keyword(e), span).unexpected("true|false|null|this"))?,
         };
         Ok(res)
     }
@@ -46,13 +63,15 @@ pub struct SubroutineCall(
     pub Option<ClassOrVarName>,
     pub SubroutineName,
     pub ExpressionList,
+    pub Span,
 );
 type ClassOrVarName = Identifier;
 
+/** The span covers both the operator and the term */
 #[derive(Debug)]
-pub struct OpTerm(pub Op, pub Term);
+pub struct OpTerm(pub Op, pub Term, pub Span);
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Op {
     Add,
     Sub,
@@ -65,6 +84,24 @@ pub enum Op {
     Eq,
 }
 
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use Op::*;
+        let c = match self {
+            Add => '+',
+            Sub => '-',
+            Mul => '*',
+            Div => '/',
+            And => '&',
+            Or => '|',
+            Lt => '<',
+            Gt => '>',
+            Eq => '=',
+        };
+        write!(f, "{c}")
+    }
+}
+
 impl Op {
     pub fn from_char(c: char) -> Option<Self> {
         use Op::*;
@@ -82,9 +119,20 @@ impl Op {
         };
         Some(x)
     }
+
+    /** Conventional precedence, higher binds tighter. Jack itself has none */
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Op::Mul | Op::Div => 4,
+            Op::Add | Op::Sub => 3,
+            Op::Lt | Op::Gt | Op::Eq => 2,
+            Op::And => 1,
+            Op::Or => 0,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum UnaryOp {
     Minus,
     Not,
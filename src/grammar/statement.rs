@@ -1,7 +1,9 @@
 use crate::grammar::expression::{Expression, SubroutineCall};
 use crate::grammar::structure::VarName;
+use crate::span::Span;
 
 pub type Statements = Vec<Statement>;
+#[allow(clippy::enum_variant_names, clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Statement {
     LetStatement(LetStatement),
@@ -10,15 +12,32 @@ pub enum Statement {
     DoStatement(DoStatement),
     ReturnStatement(ReturnStatement),
 }
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::LetStatement(s) => s.3,
+            Statement::IfStatement(s) => s.3,
+            Statement::WhileStatement(s) => s.2,
+            Statement::DoStatement(s) => s.1,
+            Statement::ReturnStatement(s) => s.1,
+        }
+    }
+}
 #[derive(Debug)]
-pub struct LetStatement(pub VarName, pub Option<Index>, pub Expression);
+pub struct LetStatement(pub VarName, pub Option<Index>, pub Expression, pub Span);
 type Index = Expression;
 #[derive(Debug)]
-pub struct IfStatement(pub Expression, pub Statements, pub Option<ElseStatement>);
+pub struct IfStatement(
+    pub Expression,
+    pub Statements,
+    pub Option<ElseStatement>,
+    pub Span,
+);
 pub type ElseStatement = Statements;
 #[derive(Debug)]
-pub struct WhileStatement(pub Expression, pub Statements);
+pub struct WhileStatement(pub Expression, pub Statements, pub Span);
 #[derive(Debug)]
-pub struct DoStatement(pub SubroutineCall);
+pub struct DoStatement(pub SubroutineCall, pub Span);
 #[derive(Debug)]
-pub struct ReturnStatement(pub Option<Expression>);
+pub struct ReturnStatement(pub Option<Expression>, pub Span);
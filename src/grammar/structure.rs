@@ -1,24 +1,32 @@
+use crate::diagnostic::Diagnostic;
 use crate::grammar::statement::Statements;
 use crate::grammar::terminal::*;
+use crate::span::Span;
+use std::fmt::{Display, Formatter};
 
 #[derive(Debug)]
-pub struct Class(pub ClassName, pub Vec<ClassVarDec>, pub Vec<SubroutineDec>);
+pub struct Class(
+    pub ClassName,
+    pub Vec<ClassVarDec>,
+    pub Vec<SubroutineDec>,
+    pub Span,
+);
 #[derive(Debug)]
-pub struct ClassVarDec(pub ClassVarType, pub Type, pub Vec<VarName>);
+pub struct ClassVarDec(pub ClassVarType, pub Type, pub Vec<VarName>, pub Span);
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum ClassVarType {
     Static,
     Field,
 }
 
 impl ClassVarType {
-    pub fn from_keyword(keyword: &Keyword) -> Result<Self, String> {
+    pub fn from_keyword(keyword: &Keyword, span: Span) -> Result<Self, Diagnostic> {
         use ClassVarType::*;
         match keyword {
             Keyword::Static => Ok(Static),
             Keyword::Field => Ok(Field),
-            e => Err(format!("expected static or field found: {e:?}")),
+            &e => Err(Token(Terminal::Keyword(e), span).unexpected("static or field")),
         }
     }
 }
@@ -31,15 +39,26 @@ pub enum Type {
     ClassName(ClassName),
 }
 
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Char => write!(f, "char"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::ClassName(name) => write!(f, "{name}"),
+        }
+    }
+}
+
 impl Type {
-    pub fn from_terminal(terminal: Terminal) -> Result<Self, String> {
+    pub fn from_token(token: Token) -> Result<Self, Diagnostic> {
         use Type::*;
-        match terminal {
+        match token.0 {
             Terminal::Keyword(Keyword::Int) => Ok(Int),
             Terminal::Keyword(Keyword::Char) => Ok(Char),
             Terminal::Keyword(Keyword::Boolean) => Ok(Boolean),
             Terminal::Identifier(val) => Ok(ClassName(val)),
-            e => Err(format!("type expected found: {e:?}")),
+            _ => Err(token.unexpected("type")),
         }
     }
 }
@@ -51,9 +70,10 @@ pub struct SubroutineDec(
     pub SubroutineName,
     pub ParameterList,
     pub SubroutineBody,
+    pub Span,
 );
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum SubroutineType {
     Constructor,
     Function,
@@ -61,41 +81,41 @@ pub enum SubroutineType {
 }
 
 impl SubroutineType {
-    pub fn from_keyword(keyword: &Keyword) -> Result<Self, String> {
+    pub fn from_keyword(keyword: &Keyword, span: Span) -> Result<Self, Diagnostic> {
         use SubroutineType::*;
         let x = match keyword {
             Keyword::Constructor => Constructor,
             Keyword::Function => Function,
             Keyword::Method => Method,
-            e => Err(format!("constructor|function|method expected found: {e:?}"))?,
+            &e => Err(Token(Terminal::Keyword(e), span).unexpected("constructor|function|method"))?,
         };
         Ok(x)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ReturnType {
     Base(Type),
     Void,
 }
 
 impl ReturnType {
-    pub fn from_terminal(tp: Terminal) -> Result<Self, String> {
+    pub fn from_token(tp: Token) -> Result<Self, Diagnostic> {
         use ReturnType::*;
-        match tp {
+        match tp.0 {
             Terminal::Keyword(Keyword::Void) => Ok(Void),
-            tp => Ok(Base(Type::from_terminal(tp)?)),
+            _ => Ok(Base(Type::from_token(tp)?)),
         }
     }
 }
 
 pub type ParameterList = Vec<Parameter>;
 #[derive(Debug)]
-pub struct Parameter(pub Type, pub Identifier);
+pub struct Parameter(pub Type, pub Identifier, pub Span);
 #[derive(Debug)]
-pub struct SubroutineBody(pub Vec<VarDec>, pub Statements);
+pub struct SubroutineBody(pub Vec<VarDec>, pub Statements, pub Span);
 #[derive(Debug)]
-pub struct VarDec(pub Type, pub Vec<VarName>);
+pub struct VarDec(pub Type, pub Vec<VarName>, pub Span);
 pub type VarName = Identifier;
 pub type SubroutineName = Identifier;
 pub type ClassName = Identifier;
@@ -1,4 +1,7 @@
+use crate::diagnostic::{Code, Diagnostic};
+use crate::span::Span;
 use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Terminal {
@@ -22,35 +25,31 @@ impl Display for Terminal {
 }
 
 impl Terminal {
-    pub fn keyword(self) -> Result<Keyword, String> {
+    // Human readable description used in the diagnostics
+    pub fn description(&self) -> String {
         match self {
-            Terminal::Keyword(val) => Ok(val),
-            e => Err(format!("keyword expected found: {e}")),
+            Terminal::Keyword(val) => format!("keyword `{val}`"),
+            Terminal::Symbol(val) => format!("`{val}`"),
+            Terminal::IntegerConstant(val) => format!("integer `{val}`"),
+            Terminal::StringConstant(val) => format!("string \"{val}\""),
+            Terminal::Identifier(val) => format!("identifier `{val}`"),
         }
     }
-    pub fn symbol(self) -> Result<char, String> {
-        match self {
-            Terminal::Symbol(val) => Ok(val),
-            e => Err(format!("symbol expected found: {e}")),
-        }
-    }
-    // pub fn integer(self) -> Result<u16, String> {
-    //     match self {
-    //         Terminal::IntegerConstant(val) => Ok(val),
-    //         e => Err(format!("integer expected found: {e}")),
-    //     }
-    // }
-    // pub fn string(self) -> Result<String, String> {
-    //     match self {
-    //         Terminal::StringConstant(val) => Ok(val),
-    //         e => Err(format!("string constant expected found: {e}")),
-    //     }
-    // }
-    pub fn identifier(self) -> Result<Identifier, String> {
-        match self {
-            Terminal::Identifier(val) => Ok(val),
-            e => Err(format!("identifier expected found: {e}")),
-        }
+}
+
+/** A terminal along with its location in the source */
+#[derive(Debug, Clone)]
+pub struct Token(pub Terminal, pub Span);
+
+impl Token {
+    // Error saying that something else was expected in place of this token
+    pub fn unexpected(&self, expected: &str) -> Diagnostic {
+        Diagnostic::error(
+            Code::UnexpectedToken,
+            self.1,
+            format!("expected {expected}, found {}", self.0.description()),
+        )
+        .with_label(format!("expected {expected}"))
     }
 }
 
@@ -116,11 +115,26 @@ impl Keyword {
     }
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Clone)]
-pub struct Identifier(pub String);
+/** Identifiers are compared by name only, the span is ignored */
+#[derive(Debug, Clone)]
+pub struct Identifier(pub String, pub Span);
+
+impl PartialEq for Identifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Identifier {}
+
+impl Hash for Identifier {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
 
 impl Display for Identifier {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Identifier({})", self.0)
+        write!(f, "{}", self.0)
     }
 }
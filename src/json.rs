@@ -0,0 +1,344 @@
+use crate::grammar::expression::{Expression, KeywordConstant, SubroutineCall, Term, UnaryOp};
+use crate::grammar::statement::{Statement, Statements};
+use crate::grammar::structure::{
+    Class, ClassVarDec, ClassVarType, Parameter, ReturnType, SubroutineDec, SubroutineType, Type,
+    VarDec,
+};
+use crate::grammar::terminal::Identifier;
+use crate::span::{Position, Span};
+use std::fmt::{Display, Formatter, Write};
+use std::path::Path;
+
+/** Version of the schema, increased whenever a field is renamed or removed */
+const VERSION: i64 = 1;
+
+/** A JSON value, objects keep the order of their fields */
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    // Values without arrays, such as spans and identifiers, are written on one line
+    fn is_inline(&self) -> bool {
+        match self {
+            Json::Array(_) => false,
+            Json::Object(fields) => fields.iter().all(|x| x.1.is_inline()),
+            _ => true,
+        }
+    }
+
+    fn write(&self, f: &mut Formatter<'_>, depth: usize) -> std::fmt::Result {
+        let indent = "  ".repeat(depth + 1);
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{value}"),
+            Json::Number(value) => write!(f, "{value}"),
+            Json::String(value) => write_string(f, value),
+            Json::Array(values) if values.is_empty() => write!(f, "[]"),
+            Json::Array(values) => {
+                writeln!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    write!(f, "{indent}")?;
+                    value.write(f, depth + 1)?;
+                    writeln!(f, "{}", if i + 1 < values.len() { "," } else { "" })?;
+                }
+                write!(f, "{}]", "  ".repeat(depth))
+            }
+            Json::Object(fields) if self.is_inline() => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    write!(f, "{}\"{key}\": ", if i > 0 { ", " } else { "" })?;
+                    value.write(f, depth)?;
+                }
+                write!(f, "}}")
+            }
+            Json::Object(fields) => {
+                writeln!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    write!(f, "{indent}\"{key}\": ")?;
+                    value.write(f, depth + 1)?;
+                    writeln!(f, "{}", if i + 1 < fields.len() { "," } else { "" })?;
+                }
+                write!(f, "{}}}", "  ".repeat(depth))
+            }
+        }
+    }
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+fn write_string(f: &mut Formatter<'_>, value: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+fn string(value: impl Display) -> Json {
+    Json::String(value.to_string())
+}
+
+fn list<T>(values: &[T], to_json: impl Fn(&T) -> Json) -> Json {
+    Json::Array(values.iter().map(to_json).collect())
+}
+
+/** Syntax tree of a class, with the file it was read from */
+pub fn class(path: &Path, class: &Class) -> Json {
+    Json::Object(vec![
+        ("version", Json::Number(VERSION)),
+        ("file", string(path.display())),
+        (
+            "class",
+            Json::Object(vec![
+                ("kind", string("class")),
+                ("name", identifier(&class.0)),
+                ("classVarDecs", list(&class.1, class_var_dec)),
+                ("subroutineDecs", list(&class.2, subroutine_dec)),
+                ("span", span(class.3)),
+            ]),
+        ),
+    ])
+}
+
+// Lines and columns start at 1, the offset counts characters from the start of the file
+fn span(span: Span) -> Json {
+    let position = |x: Position| {
+        Json::Object(vec![
+            ("line", Json::Number(x.line as i64)),
+            ("column", Json::Number(x.column as i64)),
+            ("offset", Json::Number(x.offset as i64)),
+        ])
+    };
+    Json::Object(vec![
+        ("start", position(span.start)),
+        ("end", position(span.end)),
+    ])
+}
+
+fn identifier(identifier: &Identifier) -> Json {
+    Json::Object(vec![
+        ("name", string(&identifier.0)),
+        ("span", span(identifier.1)),
+    ])
+}
+
+// int, char, boolean or a class name
+fn var_type(var_type: &Type) -> Json {
+    string(var_type)
+}
+
+fn class_var_dec(class_var_dec: &ClassVarDec) -> Json {
+    let storage = match class_var_dec.0 {
+        ClassVarType::Static => "static",
+        ClassVarType::Field => "field",
+    };
+    Json::Object(vec![
+        ("kind", string("classVarDec")),
+        ("storage", string(storage)),
+        ("type", var_type(&class_var_dec.1)),
+        ("names", list(&class_var_dec.2, identifier)),
+        ("span", span(class_var_dec.3)),
+    ])
+}
+
+fn subroutine_dec(subroutine: &SubroutineDec) -> Json {
+    let subroutine_type = match subroutine.0 {
+        SubroutineType::Constructor => "constructor",
+        SubroutineType::Function => "function",
+        SubroutineType::Method => "method",
+    };
+    let return_type = match &subroutine.1 {
+        ReturnType::Void => string("void"),
+        ReturnType::Base(return_type) => var_type(return_type),
+    };
+    let body = &subroutine.4;
+    Json::Object(vec![
+        ("kind", string("subroutineDec")),
+        ("subroutineType", string(subroutine_type)),
+        ("returnType", return_type),
+        ("name", identifier(&subroutine.2)),
+        ("parameters", list(&subroutine.3, parameter)),
+        ("varDecs", list(&body.0, var_dec)),
+        ("statements", statements(&body.1)),
+        ("bodySpan", span(body.2)),
+        ("span", span(subroutine.5)),
+    ])
+}
+
+fn parameter(parameter: &Parameter) -> Json {
+    Json::Object(vec![
+        ("type", var_type(&parameter.0)),
+        ("name", identifier(&parameter.1)),
+        ("span", span(parameter.2)),
+    ])
+}
+
+fn var_dec(var_dec: &VarDec) -> Json {
+    Json::Object(vec![
+        ("kind", string("varDec")),
+        ("type", var_type(&var_dec.0)),
+        ("names", list(&var_dec.1, identifier)),
+        ("span", span(var_dec.2)),
+    ])
+}
+
+fn statements(statements: &Statements) -> Json {
+    list(statements, statement)
+}
+
+fn optional<T>(value: &Option<T>, to_json: impl Fn(&T) -> Json) -> Json {
+    value.as_ref().map_or(Json::Null, to_json)
+}
+
+fn statement(statement: &Statement) -> Json {
+    let fields = match statement {
+        Statement::LetStatement(statement) => vec![
+            ("kind", string("let")),
+            ("name", identifier(&statement.0)),
+            ("index", optional(&statement.1, expression)),
+            ("value", expression(&statement.2)),
+        ],
+        Statement::IfStatement(statement) => vec![
+            ("kind", string("if")),
+            ("condition", expression(&statement.0)),
+            ("then", statements(&statement.1)),
+            ("else", optional(&statement.2, statements)),
+        ],
+        Statement::WhileStatement(statement) => vec![
+            ("kind", string("while")),
+            ("condition", expression(&statement.0)),
+            ("body", statements(&statement.1)),
+        ],
+        Statement::DoStatement(statement) => {
+            vec![
+                ("kind", string("do")),
+                ("call", subroutine_call(&statement.0)),
+            ]
+        }
+        Statement::ReturnStatement(statement) => vec![
+            ("kind", string("return")),
+            ("value", optional(&statement.0, expression)),
+        ],
+    };
+    with_span(fields, statement.span())
+}
+
+fn with_span(mut fields: Vec<(&'static str, Json)>, value: Span) -> Json {
+    fields.push(("span", span(value)));
+    Json::Object(fields)
+}
+
+// The first term followed by the operators in source order. With `--precedence` the parser
+// groups the tighter operators into bracket expressions that are marked as synthesized
+fn expression(expression: &Expression) -> Json {
+    let operations = list(&expression.1, |x| {
+        Json::Object(vec![
+            ("op", string(x.0)),
+            ("term", term(&x.1)),
+            ("span", span(x.2)),
+        ])
+    });
+    Json::Object(vec![
+        ("kind", string("expression")),
+        ("term", term(&expression.0)),
+        ("operations", operations),
+        ("span", span(expression.2)),
+    ])
+}
+
+fn term(term: &Term) -> Json {
+    let fields = match term {
+        Term::IntegerConstant(value, _) => vec![
+            ("kind", string("integerConstant")),
+            ("value", Json::Number(*value as i64)),
+        ],
+        Term::StringConstant(value, _) => {
+            vec![("kind", string("stringConstant")), ("value", string(value))]
+        }
+        Term::KeywordConstant(constant, _) => {
+            let value = match constant {
+                KeywordConstant::True => "true",
+                KeywordConstant::False => "false",
+                KeywordConstant::Null => "null",
+                KeywordConstant::This => "this",
+            };
+            vec![
+                ("kind", string("keywordConstant")),
+                ("value", string(value)),
+            ]
+        }
+        Term::VarName(name) => vec![("kind", string("varName")), ("name", identifier(name))],
+        Term::VarNameIndex(name, index, _) => vec![
+            ("kind", string("varNameIndex")),
+            ("name", identifier(name)),
+            ("index", expression(index)),
+        ],
+        Term::BracketExpression(inner, span) => vec![
+            ("kind", string("bracketExpression")),
+            ("expression", expression(inner)),
+            // Brackets added by the parser are not in the source
+            ("synthesized", Json::Bool(inner.2 == *span)),
+        ],
+        Term::UnaryOpTerm(op, inner, _) => {
+            let op = match op {
+                UnaryOp::Minus => "-",
+                UnaryOp::Not => "~",
+            };
+            vec![
+                ("kind", string("unaryOp")),
+                ("op", string(op)),
+                ("term", self::term(inner)),
+            ]
+        }
+        Term::SubroutineCall(call) => return subroutine_call(call),
+    };
+    with_span(fields, term.span())
+}
+
+fn subroutine_call(call: &SubroutineCall) -> Json {
+    Json::Object(vec![
+        ("kind", string("subroutineCall")),
+        ("receiver", optional(&call.0, identifier)),
+        ("name", identifier(&call.1)),
+        ("arguments", list(&call.2, expression)),
+        ("span", span(call.3)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+    use crate::source::SourceMap;
+    use std::fs::read_to_string;
+    use std::path::Path;
+
+    #[test]
+    fn snapshot() {
+        let mut sources = SourceMap::new();
+        let content = read_to_string("test/json/Precedence.jack").unwrap();
+        let file = sources.add("Precedence.jack".into(), content.chars().collect());
+        let content = &sources.get(file).unwrap().content;
+        let mut parser = Parser::new(content, file).with_precedence(true);
+        let class = parser.parse().0.unwrap();
+        let json = format!("{}\n", super::class(Path::new("Precedence.jack"), &class));
+        assert_eq!(json, read_to_string("test/json/Precedence.json").unwrap());
+    }
+}
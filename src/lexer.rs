@@ -1,24 +1,52 @@
+use crate::diagnostic::{Code, Diagnostic};
 use crate::grammar::terminal;
-use crate::grammar::terminal::Terminal;
 use crate::grammar::terminal::Terminal::{
     Identifier, IntegerConstant, Keyword, StringConstant, Symbol,
 };
+use crate::grammar::terminal::Token;
+use crate::span::{FileId, Position, Span};
+
+/** Jack character code of a new line, written `\n` in strings when escapes are enabled */
+const NEW_LINE: char = '\u{80}';
+
+/** Characters that are a token on their own */
+const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
 
 pub struct Lexer<'a> {
     pub content: &'a [char],
+    file: FileId,
+    position: Position,
+    /**start of the last token returned by next_token*/
+    token_start: Position,
+    /**invalid tokens, which are replaced by a valid one to keep parsing*/
+    pub diagnostics: Vec<Diagnostic>,
+    /**whether `\n`, `\"` and `\\` are escape sequences in strings*/
+    pub escapes: bool,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(content: &'a [char]) -> Self {
-        Self { content }
+    pub fn new(content: &'a [char], file: FileId) -> Self {
+        Self {
+            content,
+            file,
+            position: Position::start(),
+            token_start: Position::start(),
+            diagnostics: vec![],
+            escapes: false,
+        }
+    }
+
+    // Span of the end of the input, used for reporting unexpected eof
+    pub fn eof_span(&self) -> Span {
+        Span::new(self.file, self.position, self.position)
     }
 
     // Trim whitespaces from left
     pub fn trim_left(&mut self) {
         if let Some(n) = self.content.iter().position(|x| !x.is_whitespace()) {
-            self.content = &self.content[n..]
+            self.chop(n);
         } else {
-            self.content = &[]
+            self.chop(self.content.len());
         }
     }
 
@@ -26,6 +54,16 @@ impl<'a> Lexer<'a> {
     fn chop(&mut self, n: usize) -> &'a [char] {
         let token = &self.content[..n];
         self.content = &self.content[n..];
+        // Keep track of where we are in the file
+        for &c in token {
+            self.position.offset += 1;
+            if c == '\n' {
+                self.position.line += 1;
+                self.position.column = 1;
+            } else {
+                self.position.column += 1;
+            }
+        }
         token
     }
 
@@ -41,68 +79,293 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    // Get the next token ignoring the comments
-    pub fn next_token(&mut self) -> Option<&'a [char]> {
+    // Skip the whitespaces and the comments
+    fn skip_comments(&mut self) {
         loop {
-            // Ignore the comments
             self.trim_left();
-            if self.content.len() > 1 && self.content[0] == '/' && self.content[1] == '/' {
+            if self.content.starts_with(&['/', '/']) {
                 self.chop_while(|&x| !x.is_control());
-                self.trim_left()
-            } else if self.content.len() > 1 && self.content[0] == '/' && self.content[1] == '*' {
-                let mut i = 3;
-                while i < self.content.len()
-                    && (self.content[i] != '/' || self.content[i - 1] != '*')
-                {
-                    i += 1;
-                }
-                if i + 1 > self.content.len() {
-                    self.content = &[]
+            } else if self.content.starts_with(&['/', '*']) {
+                let start = self.position;
+                if let Some(i) = self.content[2..].windows(2).position(|x| x == ['*', '/']) {
+                    self.chop(i + 4);
                 } else {
-                    self.content = &self.content[i + 1..]
+                    self.chop(2);
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            Code::UnterminatedComment,
+                            Span::new(self.file, start, self.position),
+                            "unterminated block comment",
+                        )
+                        .with_label("comment starts here")
+                        .with_note("block comments end with `*/`"),
+                    );
+                    self.chop(self.content.len());
                 }
             } else {
                 break;
             }
         }
+    }
 
-        if self.content.len() == 0 {
-            return None;
-        }
+    // Get the next token ignoring the comments and the illegal characters
+    pub fn next_token(&mut self) -> Option<&'a [char]> {
+        loop {
+            self.skip_comments();
+            if self.content.is_empty() {
+                return None;
+            }
+            self.token_start = self.position;
+            let first = self.content[0];
 
-        if self.content[0].is_numeric() {
-            Some(self.chop_while(|x| x.is_numeric()))
-        } else if self.content[0].is_alphabetic() {
-            // Variable rules: can contain _
-            Some(self.chop_while(|&x| x.is_alphanumeric() || x == '_'))
-        } else {
-            Some(self.chop(1))
+            if first.is_ascii_digit() {
+                return Some(self.chop_while(|x| x.is_ascii_digit()));
+            } else if first.is_alphabetic() || first == '_' {
+                // Variable rules: can contain and start with _
+                let token = self.chop_while(|&x| x.is_alphanumeric() || x == '_');
+                if !token.iter().all(char::is_ascii) {
+                    self.non_ascii_identifier(token);
+                }
+                return Some(token);
+            } else if first == '"' || SYMBOLS.contains(first) {
+                return Some(self.chop(1));
+            }
+            // Skipped, so that the parser carries on with the next token
+            self.chop(1);
+            let mut err = Diagnostic::error(
+                Code::IllegalCharacter,
+                Span::new(self.file, self.token_start, self.position),
+                format!("unexpected character `{}`", first.escape_debug()),
+            )
+            .with_label("not valid in Jack");
+            if !first.is_ascii() {
+                err = err.with_note("Jack programs are written in ASCII");
+            }
+            self.diagnostics.push(err);
         }
     }
 
-    pub fn next_element(&mut self) -> Option<Terminal> {
-        let token = self.next_token()?.into_iter().collect::<String>();
-        let first_char = token.chars().nth(0)?;
+    // Non ASCII letters are accepted when reading identifiers, to report the whole word
+    fn non_ascii_identifier(&mut self, token: &[char]) {
+        let name = token.iter().collect::<String>();
+        self.diagnostics.push(
+            Diagnostic::error(
+                Code::NonAsciiCharacter,
+                Span::new(self.file, self.token_start, self.position),
+                format!("identifier `{name}` contains non-ASCII characters"),
+            )
+            .with_label("only ASCII letters, digits and `_` are allowed"),
+        );
+    }
+
+    fn next_element(&mut self) -> Option<Token> {
+        let token = self.next_token()?;
+        let start = self.token_start;
+        let token = token.iter().collect::<String>();
+        let first_char = token.chars().next()?;
 
-        let term = if first_char.is_numeric() {
-            IntegerConstant(token.parse().ok()?)
-        } else if first_char.is_alphanumeric() {
+        let term = if first_char.is_ascii_digit() {
+            IntegerConstant(self.integer(&token, Span::new(self.file, start, self.position)))
+        } else if first_char.is_alphanumeric() || first_char == '_' {
             if let Ok(keyword) = terminal::Keyword::from_str(&token) {
                 Keyword(keyword)
             } else {
-                Identifier(terminal::Identifier(token))
+                Identifier(terminal::Identifier(
+                    token,
+                    Span::new(self.file, start, self.position),
+                ))
             }
         } else if first_char == '"' {
-            let st = self
-                .chop_while(|&x| x != '"')
-                .into_iter()
-                .collect::<String>();
-            self.content = &self.content[1..];
-            StringConstant(st)
+            StringConstant(self.string(start))
         } else {
             Symbol(first_char)
         };
 
-        Some(term)
+        Some(Token(term, Span::new(self.file, start, self.position)))
+    }
+
+    // Rest of a string after the opening quote, which cannot span several lines
+    fn string(&mut self, start: Position) -> String {
+        let mut res = String::new();
+        loop {
+            match self.content.first() {
+                Some('"') => {
+                    self.chop(1);
+                    return res;
+                }
+                None | Some('\n' | '\r') => {
+                    let mut err = Diagnostic::error(
+                        Code::UnterminatedString,
+                        Span::new(self.file, start, self.position),
+                        "unterminated string literal",
+                    )
+                    .with_label("missing closing `\"`");
+                    if !self.content.is_empty() {
+                        err = err.with_note("strings cannot contain line breaks");
+                    }
+                    self.diagnostics.push(err);
+                    return res;
+                }
+                Some('\\') if self.escapes => {
+                    let escape_start = self.position;
+                    self.chop(1);
+                    let escaped = match self.content.first() {
+                        Some('n') => Some(NEW_LINE),
+                        Some(&c @ ('"' | '\\')) => Some(c),
+                        _ => None,
+                    };
+                    match escaped {
+                        Some(c) => {
+                            self.chop(1);
+                            res.push(c);
+                        }
+                        None => {
+                            // The line break is left to end the string
+                            if self
+                                .content
+                                .first()
+                                .is_some_and(|&x| x != '\n' && x != '\r')
+                            {
+                                self.chop(1);
+                            }
+                            self.diagnostics.push(
+                                Diagnostic::error(
+                                    Code::InvalidEscape,
+                                    Span::new(self.file, escape_start, self.position),
+                                    "unknown escape sequence",
+                                )
+                                .with_label("not a valid escape")
+                                .with_note("the escape sequences are `\\n`, `\\\"` and `\\\\`"),
+                            );
+                        }
+                    }
+                }
+                Some(&c) => {
+                    let char_start = self.position;
+                    self.chop(1);
+                    res.push(c);
+                    if !c.is_ascii() {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                Code::NonAsciiCharacter,
+                                Span::new(self.file, char_start, self.position),
+                                format!("character `{c}` is not in the Jack character set"),
+                            )
+                            .with_label("not an ASCII character"),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Jack integers are signed 16 bits, so literals stop at 32767
+    fn integer(&mut self, token: &str, span: Span) -> u16 {
+        if let Some(value) = token.parse().ok().filter(|&x| x <= 32767) {
+            return value;
+        }
+        let mut err = Diagnostic::error(
+            Code::IntegerOutOfRange,
+            span,
+            format!("integer `{token}` is out of range"),
+        )
+        .with_label("must be between 0 and 32767");
+        if token.trim_start_matches('0') == "32768" {
+            err = err.with_note("write `-32767 - 1` for the smallest integer");
+        }
+        self.diagnostics.push(err);
+        0
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.next_element()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lexer, NEW_LINE};
+    use crate::diagnostic::Code;
+    use crate::grammar::terminal::{Keyword, Terminal};
+
+    // The terminals of the source and the codes of the diagnostics, with their columns
+    fn lex(source: &str, escapes: bool) -> (Vec<Terminal>, Vec<(Code, usize)>) {
+        let content = source.chars().collect::<Vec<_>>();
+        let mut lexer = Lexer::new(&content, 0);
+        lexer.escapes = escapes;
+        let terminals = lexer.by_ref().map(|x| x.0).collect();
+        let diagnostics = lexer
+            .diagnostics
+            .iter()
+            .map(|x| (x.code, x.span.start.column))
+            .collect();
+        (terminals, diagnostics)
+    }
+
+    fn string(value: &str) -> Terminal {
+        Terminal::StringConstant(value.into())
+    }
+
+    #[test]
+    fn illegal_characters_are_skipped() {
+        let (terminals, diagnostics) = lex("let x = 1 # 2 @;", false);
+        assert_eq!(terminals.len(), 6);
+        assert_eq!(terminals[4], Terminal::IntegerConstant(2));
+        let expected = [(Code::IllegalCharacter, 11), (Code::IllegalCharacter, 15)];
+        assert_eq!(diagnostics, expected);
+    }
+
+    #[test]
+    fn non_ascii_text() {
+        let (terminals, diagnostics) = lex("let café = \"é\";", false);
+        assert_eq!(terminals[0], Terminal::Keyword(Keyword::Let));
+        assert!(matches!(&terminals[1], Terminal::Identifier(x) if x.0 == "café"));
+        assert_eq!(terminals[3], string("é"));
+        let expected = [(Code::NonAsciiCharacter, 5), (Code::NonAsciiCharacter, 13)];
+        assert_eq!(diagnostics, expected);
+        let (_, diagnostics) = lex("x → y", false);
+        assert_eq!(diagnostics, [(Code::IllegalCharacter, 3)]);
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let (terminals, diagnostics) = lex("return; /* no end\nreturn;", false);
+        assert_eq!(terminals.len(), 2);
+        assert_eq!(diagnostics, [(Code::UnterminatedComment, 9)]);
+        let (terminals, diagnostics) = lex("/** doc */ return /* */;", false);
+        assert_eq!(terminals.len(), 2);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn unterminated_string() {
+        // The string ends at the line break and the next line is read as usual
+        let (terminals, diagnostics) = lex("\"abc\nreturn", false);
+        assert_eq!(
+            terminals,
+            [string("abc"), Terminal::Keyword(Keyword::Return)]
+        );
+        assert_eq!(diagnostics, [(Code::UnterminatedString, 1)]);
+        let (terminals, diagnostics) = lex("x \"abc", false);
+        assert_eq!(terminals[1], string("abc"));
+        assert_eq!(diagnostics, [(Code::UnterminatedString, 3)]);
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let source = r#""a\nb\"c\\d""#;
+        let (terminals, diagnostics) = lex(source, true);
+        assert_eq!(terminals, [string(&format!("a{NEW_LINE}b\"c\\d"))]);
+        assert!(diagnostics.is_empty());
+        // Without escapes a backslash is an ordinary character and `\"` ends the string
+        let (terminals, _) = lex(r#""a\nb""#, false);
+        assert_eq!(terminals, [string(r"a\nb")]);
+        let (terminals, diagnostics) = lex(r#""a\tb""#, true);
+        assert_eq!(terminals, [string("ab")]);
+        assert_eq!(diagnostics, [(Code::InvalidEscape, 3)]);
     }
 }
@@ -1,19 +1,62 @@
+use crate::analysis::program::ProgramIndex;
+use crate::analysis::type_check::TypeChecker;
+use crate::analysis::{constant, constructor, flow, lint};
+use crate::backend::assembler::assemble;
+use crate::backend::{source_lines, translate_program};
+use crate::cli::{Emit, Options, USAGE};
 use crate::compiler::Compiler;
+use crate::diagnostic::Diagnostic;
+use crate::emulator::keyboard::parse_key_script;
+use crate::emulator::Emulator;
+use crate::lexer::Lexer;
+use crate::optimizer::unused::remove_unused_functions;
 use crate::parser::Parser;
+use crate::source::SourceMap;
+use crate::span::FileId;
+use crate::vm::VmCommand;
 use std::env::args;
 use std::fs::{read_dir, File};
-use std::io::{Read, Write};
-use std::path::Path;
+use std::io::{stdin, stdout, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
+mod analysis;
+mod backend;
+mod cli;
 mod compiler;
+mod diagnostic;
+mod emulator;
 mod grammar;
+mod json;
 mod lexer;
+mod optimizer;
 mod parser;
+mod source;
+mod span;
 mod symbol_table;
+mod token_stream;
+mod vm;
+mod xml;
 
-fn main() -> Result<(), String> {
-    let path_input = args().nth(1).expect("Usage: cargo run <filename>");
-    let path = Path::new(&path_input);
+fn main() -> ExitCode {
+    let options = match Options::from_args(args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let path = options.path.as_path();
+
+    // Assembly written by hand goes straight to the assembler
+    if path.extension().is_some_and(|x| x == "asm") {
+        let mut sources = SourceMap::new();
+        let diagnostics = assemble_file(path, &mut sources)
+            .err()
+            .into_iter()
+            .collect();
+        return report(diagnostics, &sources);
+    }
 
     let file_paths: Vec<_>;
 
@@ -22,7 +65,7 @@ fn main() -> Result<(), String> {
         file_paths = files
             .filter_map(|entry| {
                 let path = entry.ok()?.path();
-                if path.is_file() && path.extension().unwrap() == "jack" {
+                if path.is_file() && path.extension()? == "jack" {
                     Some(path)
                 } else {
                     None
@@ -40,23 +83,258 @@ fn main() -> Result<(), String> {
         file_paths = vec![path.with_extension("jack")];
     }
 
+    let mut sources = SourceMap::new();
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+
+    // Parse all the files first so that the classes can refer to each other
+    let mut classes = vec![];
+    let mut partial_classes = vec![];
+    let mut unparsed_classes = vec![];
+    let mut vm_files = vec![];
     for file in file_paths {
         let mut data = String::new();
         File::open(&file)
             .unwrap()
             .read_to_string(&mut data)
             .unwrap();
-        let content = data.chars().collect::<Vec<char>>();
+        let file_id = sources.add(file.clone(), data.chars().collect());
+        let content = &sources.get(file_id).unwrap().content;
+
+        if options.emit == Emit::TokensXml && !options.run {
+            let mut lexer = Lexer::new(content, file_id);
+            lexer.escapes = options.escapes;
+            let tokens = lexer.by_ref().collect::<Vec<_>>();
+            if !lexer.diagnostics.iter().any(|x| x.is_error()) {
+                let mut output = File::create(xml_path(&file, "T")).unwrap();
+                write!(output, "{}", xml::tokens(&tokens)).unwrap();
+            }
+            diagnostics.append(&mut lexer.diagnostics);
+            continue;
+        }
 
-        let mut parser = Parser::new(&content);
+        let mut parser = Parser::new(content, file_id)
+            .with_precedence(options.precedence)
+            .with_escapes(options.escapes);
+        let (class, mut parse_diagnostics) = parser.parse();
+        let has_errors = parse_diagnostics.iter().any(|x| x.is_error());
+        diagnostics.append(&mut parse_diagnostics);
+        // A partially parsed class is only good for reporting errors, but the other classes
+        // can still refer to it
+        match class {
+            Some(class) if !has_errors => classes.push((file, class)),
+            Some(class) => partial_classes.push(class),
+            None => unparsed_classes.push(unit_name(&file)),
+        }
+    }
 
-        let mut file = File::create(file.with_extension("vm")).unwrap();
+    // The syntax outputs stop before the checks and the code generation
+    if matches!(
+        options.emit,
+        Emit::TokensXml | Emit::ParseXml | Emit::AstJson
+    ) && !options.run
+    {
+        for (file, class) in &classes {
+            let (output, text) = if options.emit == Emit::AstJson {
+                (
+                    file.with_extension("json"),
+                    format!("{}\n", json::class(file, class)),
+                )
+            } else {
+                (xml_path(file, ""), xml::class(class))
+            };
+            let mut output = File::create(output).unwrap();
+            write!(output, "{text}").unwrap();
+        }
+        return report(diagnostics, &sources);
+    }
+
+    // Calls between classes can only be checked when compiling a whole directory
+    let index = path.is_dir().then(|| {
+        let mut index = ProgramIndex::new();
+        classes.iter().for_each(|(_, class)| index.add_class(class));
+        partial_classes
+            .iter()
+            .for_each(|x| index.add_partial_class(x));
+        unparsed_classes
+            .iter()
+            .for_each(|x| index.add_unparsed_class(x));
+        index
+    });
+
+    for (file, class) in classes {
+        let mut check_diagnostics =
+            TypeChecker::new(options.strict, index.as_ref()).check_class(&class);
+        if let Some(index) = &index {
+            check_diagnostics.append(&mut index.check_class(&class));
+        }
+        check_diagnostics.append(&mut constructor::check_class(&class));
+        check_diagnostics.append(&mut flow::check_class(&class));
+        check_diagnostics.append(&mut constant::check_class(&class));
+        check_diagnostics.append(&mut lint::check_class(&class));
+        let has_errors = check_diagnostics.iter().any(|x| x.is_error());
+        diagnostics.append(&mut check_diagnostics);
 
-        let class = parser.next_class()?;
         let mut compiler = Compiler::new();
-        let commands = compiler.compile_class(class)?;
+        let commands = compiler.compile_class(class);
+        let has_errors = has_errors || compiler.diagnostics.iter().any(|x| x.is_error());
+        diagnostics.append(&mut compiler.diagnostics);
+        if has_errors {
+            continue;
+        }
+        let commands = if options.optimize {
+            optimizer::optimize(commands)
+        } else {
+            commands
+        };
+        vm_files.push((file.with_extension("vm"), commands));
+    }
+
+    if options.emit == Emit::Vm && !options.run {
+        for (path, commands) in &vm_files {
+            let mut file = File::create(path).unwrap();
+            write!(file, "{}", vm::to_text(commands)).unwrap();
+        }
+    } else if !diagnostics.iter().any(|x| x.is_error()) {
+        // The whole program is only translated or run when every class compiled
+        let mut units = vm_files
+            .into_iter()
+            .map(|(path, commands)| (unit_name(&path), commands))
+            .collect::<Vec<_>>();
+        if path.is_dir() {
+            // Compiled OS classes are put next to the program as .vm files
+            match os_units(path, &mut sources) {
+                Ok(mut os_units) => units.append(&mut os_units),
+                Err(e) => diagnostics.push(e),
+            }
+        }
+        if options.remove_unused && path.is_dir() {
+            remove_unused_functions(&mut units);
+        }
+        let key_events = options.keys.as_ref().map(|path| {
+            let file_id = add_file(path, &mut sources);
+            parse_key_script(sources.get(file_id).unwrap(), file_id)
+        });
+        match key_events.transpose() {
+            Err(e) => diagnostics.push(e),
+            _ if diagnostics.iter().any(|x| x.is_error()) => {}
+            Ok(key_events) if options.run => {
+                let mut input = stdin().lock();
+                let mut output = stdout().lock();
+                let result =
+                    Emulator::load(&units, &mut input, &mut output).and_then(|mut emulator| {
+                        emulator.set_key_events(key_events.unwrap_or_default());
+                        for (step, path) in options.snapshots {
+                            emulator.add_snapshot(step, path);
+                        }
+                        emulator.run(options.max_steps)
+                    });
+                if let Err(e) = result {
+                    // Diagnostics about the source are printed first
+                    report(diagnostics, &sources);
+                    eprintln!("error: {e}");
+                    return ExitCode::FAILURE;
+                }
+            }
+            Ok(_) => {
+                let asm = match translate_program(&units, path.is_dir()) {
+                    Ok(asm) => asm,
+                    Err(mut errors) => {
+                        diagnostics.append(&mut errors);
+                        return report(diagnostics, &sources);
+                    }
+                };
+                let result = if options.emit == Emit::Asm {
+                    Ok((output_path(path, "asm"), asm))
+                } else {
+                    // The assembly is registered so that errors can point into it
+                    let asm_path = output_path(path, "asm");
+                    let file_id = sources.add(asm_path, asm.join("\n").chars().collect());
+                    let lines = source_lines(sources.get(file_id).unwrap(), file_id);
+                    assemble(&lines).map(|hack| (output_path(path, "hack"), hack))
+                };
+                match result {
+                    Ok((output, lines)) => {
+                        let mut file = File::create(output).unwrap();
+                        writeln!(file, "{}", lines.join("\n")).unwrap();
+                    }
+                    Err(e) => diagnostics.push(e),
+                }
+            }
+        }
+    }
+
+    report(diagnostics, &sources)
+}
 
-        writeln!(file, "{}", commands.join("\n")).unwrap();
+// Print the diagnostics in source order and fail if there are errors
+fn report(mut diagnostics: Vec<Diagnostic>, sources: &SourceMap) -> ExitCode {
+    diagnostics.sort_by_key(|x| (x.span.file, x.span.start.offset));
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic.render(sources));
     }
+    if diagnostics.iter().any(|x| x.is_error()) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn assemble_file(path: &Path, sources: &mut SourceMap) -> Result<(), Diagnostic> {
+    let file_id = add_file(path, sources);
+    let hack = assemble(&source_lines(sources.get(file_id).unwrap(), file_id))?;
+    let mut file = File::create(path.with_extension("hack")).unwrap();
+    writeln!(file, "{}", hack.join("\n")).unwrap();
     Ok(())
 }
+
+// Output of a whole program, Dir/Dir.ext for a directory
+fn output_path(path: &Path, extension: &str) -> PathBuf {
+    if path.is_dir() {
+        let name = path.canonicalize().unwrap();
+        let name = name.file_name().unwrap();
+        path.join(name).with_extension(extension)
+    } else {
+        path.with_extension(extension)
+    }
+}
+
+// Xxx.jack gives Xxx<suffix>.xml, as named by the course
+fn xml_path(path: &Path, suffix: &str) -> PathBuf {
+    path.with_file_name(format!("{}{suffix}.xml", unit_name(path)))
+}
+
+// .vm files in the directory that do not come from one of its .jack files
+fn os_units(
+    dir: &Path,
+    sources: &mut SourceMap,
+) -> Result<Vec<(String, Vec<VmCommand>)>, Diagnostic> {
+    let mut paths = read_dir(dir)
+        .unwrap()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            (path.extension()? == "vm" && !path.with_extension("jack").exists()).then_some(path)
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let file_id = add_file(&path, sources);
+            Ok((
+                unit_name(&path),
+                vm::parse(sources.get(file_id).unwrap(), file_id)?,
+            ))
+        })
+        .collect()
+}
+
+fn add_file(path: &Path, sources: &mut SourceMap) -> FileId {
+    let mut data = String::new();
+    File::open(path).unwrap().read_to_string(&mut data).unwrap();
+    sources.add(path.to_path_buf(), data.chars().collect())
+}
+
+// Static variables of a VM file are named after the file
+fn unit_name(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().into()
+}
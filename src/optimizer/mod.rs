@@ -0,0 +1,209 @@
+use crate::vm::VmCommand;
+
+pub mod peephole;
+pub mod unused;
+
+/** Shrink the VM code of a class without changing what it does */
+pub fn optimize(mut commands: Vec<VmCommand>) -> Vec<VmCommand> {
+    // Each pass can give the others more to remove
+    loop {
+        let res = peephole::simplify(commands.clone());
+        let res = peephole::remove_unreachable(res);
+        let res = peephole::remove_unused_labels(res);
+        if res == commands {
+            return res;
+        }
+        commands = res;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::optimize;
+    use super::unused::remove_unused_functions;
+    use crate::compiler::Compiler;
+    use crate::emulator::keyboard::KeyEvent;
+    use crate::emulator::screen::{HEIGHT, WIDTH};
+    use crate::emulator::Emulator;
+    use crate::parser::Parser;
+    use crate::source::SourceMap;
+    use crate::vm::VmCommand;
+    use std::fs::{read_dir, read_to_string};
+
+    type Units = Vec<(String, Vec<VmCommand>)>;
+
+    fn compile(dir: &str) -> Units {
+        let mut sources = SourceMap::new();
+        let mut paths = read_dir(format!("test/{dir}"))
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .filter(|x| x.extension().is_some_and(|x| x == "jack"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+            .into_iter()
+            .map(|path| {
+                let content = read_to_string(&path).unwrap().chars().collect();
+                let file = sources.add(path.clone(), content);
+                let content = &sources.get(file).unwrap().content;
+                let class = Parser::new(content, file).parse().0.unwrap();
+                let commands = Compiler::new().compile_class(class);
+                let unit = path.file_stem().unwrap().to_string_lossy().into();
+                (unit, commands)
+            })
+            .collect()
+    }
+
+    /** What a run leaves behind: the printed text, the screen and some of the RAM */
+    #[derive(Debug, PartialEq)]
+    struct Outcome {
+        output: String,
+        pixels: Vec<bool>,
+        ram: Vec<i16>,
+    }
+
+    fn run(units: &Units, input: &str, keys: Vec<KeyEvent>, ram: &[(usize, i16)]) -> Outcome {
+        let mut input = input.as_bytes();
+        let mut output = vec![];
+        let mut emulator = Emulator::load(units, &mut input, &mut output).unwrap();
+        for &(address, value) in ram {
+            emulator.ram[address] = value;
+        }
+        emulator.set_key_events(keys);
+        emulator.run(10_000_000).unwrap();
+        let pixels = (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| emulator.pixel(x, y))
+            .collect();
+        let ram = emulator.ram[8000..8100].to_vec();
+        drop(emulator);
+        Outcome {
+            output: String::from_utf8(output).unwrap(),
+            pixels,
+            ram,
+        }
+    }
+
+    fn size(units: &Units) -> usize {
+        units.iter().map(|x| x.1.len()).sum()
+    }
+
+    // Runs the program before and after the optimization and compares the outcomes,
+    // returns the number of commands removed
+    fn check(dir: &str, input: &str, keys: Vec<KeyEvent>, ram: &[(usize, i16)]) -> usize {
+        let units = compile(dir);
+        let optimized = units
+            .iter()
+            .map(|(unit, commands)| (unit.clone(), optimize(commands.clone())))
+            .collect::<Units>();
+        assert!(size(&optimized) <= size(&units), "{dir} got bigger");
+        assert_eq!(
+            run(&units, input, keys.clone(), ram),
+            run(&optimized, input, keys, ram)
+        );
+        size(&units) - size(&optimized)
+    }
+
+    // A key held from the start, the games quit at their first key check
+    fn press(key: i16) -> Vec<KeyEvent> {
+        vec![
+            KeyEvent { step: 0, key },
+            KeyEvent {
+                step: 100_000,
+                key: 0,
+            },
+        ]
+    }
+
+    #[test]
+    fn seven() {
+        // Straight line code without branches has nothing to remove
+        assert_eq!(check("Seven", "", vec![], &[]), 0);
+    }
+
+    #[test]
+    fn average() {
+        assert_eq!(check("Average", "4\n10\n-3\n7\n2\n", vec![], &[]), 0);
+    }
+
+    #[test]
+    fn complex_arrays() {
+        assert!(check("ComplexArrays", "", vec![], &[]) > 0);
+    }
+
+    #[test]
+    fn convert_to_bin() {
+        assert!(check("ConvertToBin", "", vec![], &[(8000, 0x5a3c)]) > 0);
+    }
+
+    #[test]
+    fn square() {
+        assert!(check("Square", "", press('Q' as i16), &[]) > 0);
+    }
+
+    #[test]
+    fn pong() {
+        // Esc ends the game
+        assert!(check("Pong", "", press(140), &[]) > 0);
+    }
+
+    #[test]
+    fn constant_branches() {
+        use crate::vm::{ArithmeticOp::*, Segment::*, VmCommand::*};
+        let commands = vec![
+            Function("Main.main".into(), 0),
+            Push(Constant, 0),
+            Arithmetic(Not),
+            Arithmetic(Not),
+            IfGoto("ELSE".into()),
+            Push(Local, 0),
+            Pop(Local, 0),
+            Goto("END".into()),
+            Label("ELSE".into()),
+            Push(Constant, 1),
+            Label("END".into()),
+            Push(Constant, 0),
+            Arithmetic(Neg),
+            Return,
+            Push(Constant, 2),
+        ];
+        let expected = vec![Function("Main.main".into(), 0), Push(Constant, 0), Return];
+        assert_eq!(optimize(commands), expected);
+    }
+
+    #[test]
+    fn unused_functions() {
+        use crate::vm::{Segment::*, VmCommand::*};
+        let function = |name: &str, calls: &[&str]| {
+            let mut res = vec![Function(name.into(), 0)];
+            res.extend(calls.iter().map(|x| Call(x.to_string(), 0)));
+            res.extend([Push(Constant, 0), Return]);
+            res
+        };
+        let mut units = vec![
+            (
+                "Main".into(),
+                [
+                    function("Main.main", &["Main.used", "Output.println"]),
+                    function("Main.unused", &["Other.unused"]),
+                    function("Main.used", &["Main.used", "Other.used"]),
+                ]
+                .concat(),
+            ),
+            (
+                "Other".into(),
+                [function("Other.used", &[]), function("Other.unused", &[])].concat(),
+            ),
+        ];
+        remove_unused_functions(&mut units);
+        let names = units
+            .iter()
+            .flat_map(|x| &x.1)
+            .filter_map(|x| match x {
+                Function(name, _) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Main.main", "Main.used", "Other.used"]);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::vm::{push_constant, ArithmeticOp, Segment, VmCommand};
+use std::collections::HashSet;
+
+/** Rewrite short sequences of commands, looking at the end of the commands kept so far */
+pub fn simplify(commands: Vec<VmCommand>) -> Vec<VmCommand> {
+    let mut res = vec![];
+    for command in commands {
+        res.push(command);
+        while simplify_tail(&mut res) {}
+    }
+    res
+}
+
+// Returns whether the end of the commands changed
+fn simplify_tail(res: &mut Vec<VmCommand>) -> bool {
+    let n = res.len();
+    match &res[n.saturating_sub(2)..] {
+        // Reading a value and writing it back. The other way round, `pop x` then `push x`, is
+        // kept: it is how the VM language copies the top of the stack, since there is no `dup`,
+        // and the value stored in x can still be read later, as `double` in the compiler does
+        [VmCommand::Push(s1, i1), VmCommand::Pop(s2, i2)] if s1 == s2 && i1 == i2 => {
+            res.truncate(n - 2);
+            true
+        }
+        [VmCommand::Arithmetic(ArithmeticOp::Not), VmCommand::Arithmetic(ArithmeticOp::Not)]
+        | [VmCommand::Arithmetic(ArithmeticOp::Neg), VmCommand::Arithmetic(ArithmeticOp::Neg)] => {
+            res.truncate(n - 2);
+            true
+        }
+        [.., VmCommand::Arithmetic(op @ (ArithmeticOp::Neg | ArithmeticOp::Not))] => {
+            let op = *op;
+            let Some((value, length)) = constant_at_end(&res[..n - 1]) else {
+                return false;
+            };
+            let value = if op == ArithmeticOp::Neg {
+                value.wrapping_neg()
+            } else {
+                !value
+            };
+            replace_tail(res, length + 1, push_constant(value))
+        }
+        // A branch on a constant is either always or never taken
+        [.., VmCommand::IfGoto(label)] => {
+            let label = label.clone();
+            let Some((value, length)) = constant_at_end(&res[..n - 1]) else {
+                return false;
+            };
+            let jump = if value != 0 {
+                vec![VmCommand::Goto(label)]
+            } else {
+                vec![]
+            };
+            replace_tail(res, length + 1, jump)
+        }
+        // A jump to the labels that follow it
+        [.., VmCommand::Label(_)] => {
+            let start = res.iter().rposition(|x| !matches!(x, VmCommand::Label(_)));
+            let Some(start) = start else {
+                return false;
+            };
+            let VmCommand::Goto(target) = &res[start] else {
+                return false;
+            };
+            if res[start + 1..].contains(&VmCommand::Label(target.clone())) {
+                res.remove(start);
+                true
+            } else {
+                false
+            }
+        }
+        _ => false,
+    }
+}
+
+fn replace_tail(res: &mut Vec<VmCommand>, length: usize, commands: Vec<VmCommand>) -> bool {
+    if res[res.len() - length..] == commands[..] {
+        return false;
+    }
+    res.truncate(res.len() - length);
+    res.extend(commands);
+    true
+}
+
+// A constant pushed by the last commands, with the number of commands
+fn constant_at_end(commands: &[VmCommand]) -> Option<(i16, usize)> {
+    let n = commands.len();
+    match &commands[n.saturating_sub(2)..] {
+        [VmCommand::Push(Segment::Constant, c), VmCommand::Arithmetic(ArithmeticOp::Neg)] => {
+            Some(((*c as i16).wrapping_neg(), 2))
+        }
+        [VmCommand::Push(Segment::Constant, c), VmCommand::Arithmetic(ArithmeticOp::Not)] => {
+            Some((!(*c as i16), 2))
+        }
+        [.., VmCommand::Push(Segment::Constant, c)] => Some((*c as i16, 1)),
+        _ => None,
+    }
+}
+
+/** Remove the commands after a goto or a return that no label leads to */
+pub fn remove_unreachable(commands: Vec<VmCommand>) -> Vec<VmCommand> {
+    let mut res = vec![];
+    let mut reachable = true;
+    for command in commands {
+        if matches!(command, VmCommand::Label(_) | VmCommand::Function(..)) {
+            reachable = true;
+        }
+        if reachable {
+            reachable = !matches!(command, VmCommand::Goto(_) | VmCommand::Return);
+            res.push(command);
+        }
+    }
+    res
+}
+
+/** Remove the labels that are not the target of a jump in their function */
+pub fn remove_unused_labels(commands: Vec<VmCommand>) -> Vec<VmCommand> {
+    let mut res = vec![];
+    let mut start = 0;
+    while start < commands.len() {
+        let end = commands[start + 1..]
+            .iter()
+            .position(|x| matches!(x, VmCommand::Function(..)))
+            .map_or(commands.len(), |x| start + 1 + x);
+        let function = &commands[start..end];
+        let targets = function
+            .iter()
+            .filter_map(|x| match x {
+                VmCommand::Goto(label) | VmCommand::IfGoto(label) => Some(label),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        res.extend(
+            function
+                .iter()
+                .filter(|x| !matches!(x, VmCommand::Label(label) if !targets.contains(label)))
+                .cloned(),
+        );
+        start = end;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remove_unreachable, remove_unused_labels, simplify};
+    use crate::vm::VmCommand;
+
+    fn commands(text: &str) -> Vec<VmCommand> {
+        text.lines().map(|x| x.trim().parse().unwrap()).collect()
+    }
+
+    fn assert_simplified(from: &str, to: &str) {
+        assert_eq!(simplify(commands(from)), commands(to));
+    }
+
+    #[test]
+    fn push_then_pop() {
+        assert_simplified(
+            "push local 0\npop local 0\npush argument 1",
+            "push argument 1",
+        );
+        assert_simplified("push local 0\npop local 1", "push local 0\npop local 1");
+    }
+
+    #[test]
+    fn pop_then_push_is_kept() {
+        // temp 1 is read again, the pair copies the value on the stack
+        let dup = "pop temp 1\npush temp 1\npush temp 1\nadd";
+        assert_simplified(dup, dup);
+    }
+
+    #[test]
+    fn neg_and_not() {
+        assert_simplified("push local 0\nnot\nnot", "push local 0");
+        assert_simplified("push local 0\nneg\nneg", "push local 0");
+        assert_simplified("push constant 0\nnot", "push constant 1\nneg");
+        assert_simplified("push constant 1\nneg\nnot", "push constant 0");
+        assert_simplified("push constant 5\nneg\nneg", "push constant 5");
+        assert_simplified("push local 0\nneg", "push local 0\nneg");
+    }
+
+    #[test]
+    fn if_goto_on_constant() {
+        assert_simplified("push constant 0\nif-goto L", "");
+        assert_simplified("push constant 1\nneg\nif-goto L", "goto L");
+        assert_simplified("push constant 0\nnot\nif-goto L", "goto L");
+        assert_simplified("push local 0\nif-goto L", "push local 0\nif-goto L");
+    }
+
+    #[test]
+    fn goto_next_label() {
+        assert_simplified("goto L\nlabel L", "label L");
+        assert_simplified("goto L\nlabel K\nlabel L", "label K\nlabel L");
+        assert_simplified("goto L\nlabel K\nreturn", "goto L\nlabel K\nreturn");
+    }
+
+    #[test]
+    fn unreachable_and_unused() {
+        let code = commands("function F.f 0\ngoto L\npush constant 1\nlabel L\nlabel K\nreturn");
+        assert_eq!(
+            remove_unused_labels(remove_unreachable(code)),
+            commands("function F.f 0\ngoto L\nlabel L\nreturn")
+        );
+    }
+}
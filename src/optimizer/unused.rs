@@ -0,0 +1,54 @@
+use crate::vm::VmCommand;
+use std::collections::{HashMap, HashSet};
+
+/** Remove the functions of a whole program that cannot be reached from its entry point */
+pub fn remove_unused_functions(units: &mut [(String, Vec<VmCommand>)]) {
+    // Functions called by each function of the program
+    let mut calls: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut function = None;
+    for command in units.iter().flat_map(|x| &x.1) {
+        match command {
+            VmCommand::Function(name, _) => {
+                function = Some(name.as_str());
+                calls.entry(name).or_default();
+            }
+            VmCommand::Call(name, _) => {
+                if let Some(function) = function {
+                    calls.entry(function).or_default().push(name);
+                }
+            }
+            _ => {}
+        }
+    }
+    // The same entry point as the bootstrap code
+    let Some(entry) = ["Sys.init", "Main.main"]
+        .into_iter()
+        .find(|x| calls.contains_key(x))
+    else {
+        return;
+    };
+    let mut reachable = HashSet::from([entry]);
+    let mut pending = vec![entry];
+    while let Some(function) = pending.pop() {
+        for &callee in &calls[function] {
+            // Calls to functions outside of the program go to the native OS
+            if calls.contains_key(callee) && reachable.insert(callee) {
+                pending.push(callee);
+            }
+        }
+    }
+
+    let reachable = reachable
+        .into_iter()
+        .map(String::from)
+        .collect::<HashSet<_>>();
+    for (_, commands) in units {
+        let mut keep = true;
+        commands.retain(|command| {
+            if let VmCommand::Function(name, _) = command {
+                keep = reachable.contains(name);
+            }
+            keep
+        });
+    }
+}
@@ -1,3 +1,4 @@
+use crate::diagnostic::{Code, Diagnostic};
 use crate::grammar::expression::{
     Expression, ExpressionList, KeywordConstant, Op, OpTerm, SubroutineCall, Term, UnaryOp,
 };
@@ -9,69 +10,126 @@ use crate::grammar::structure::{
     SubroutineDec, SubroutineType, Type, VarDec,
 };
 use crate::grammar::terminal::Terminal::{IntegerConstant, StringConstant, Symbol};
-use crate::grammar::terminal::{Identifier, Keyword, Terminal};
+use crate::grammar::terminal::{Identifier, Keyword, Terminal, Token};
 use crate::lexer::Lexer;
+use crate::span::{FileId, Span};
+use crate::token_stream::TokenStream;
 
 pub struct Parser<'a> {
-    lexer: Lexer<'a>,
-    pending_elements: Vec<Terminal>,
+    tokens: TokenStream<'a>,
+    /**errors recovered from while parsing*/
+    diagnostics: Vec<Diagnostic>,
+    /**group operators by their conventional precedence instead of left to right*/
+    precedence: bool,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(content: &'a [char]) -> Self {
+    pub fn new(content: &'a [char], file: FileId) -> Self {
         Self {
-            lexer: Lexer::new(content),
-            pending_elements: vec![],
+            tokens: TokenStream::new(Lexer::new(content, file)),
+            diagnostics: vec![],
+            precedence: false,
         }
     }
 
-    /** 'class' className '{' classVarDec* subroutineDec* '}' */
-    pub fn next_class(&mut self) -> Result<Class, String> {
-        self.consume(Terminal::Keyword(Keyword::Class))?;
-        let class_name = self
-            .next_element()
-            .ok_or("expected class name found eof")?
-            .identifier()?;
-
-        let mut res = Class(class_name, vec![], vec![]);
-
-        self.consume(Symbol('{'))?;
-        while let Some(ele) = self.next_element() {
-            let keyword = ele.keyword()?;
-            if let Ok(class_var_type) = ClassVarType::from_keyword(&keyword) {
-                res.1.push(self.next_class_var_dec(class_var_type)?);
+    /** Read `\n`, `\"` and `\\` in strings as escape sequences */
+    pub fn with_escapes(mut self, escapes: bool) -> Self {
+        self.tokens.lexer_mut().escapes = escapes;
+        self
+    }
+
+    /** Parse `a + b * c` as `a + (b * c)` instead of the left to right order of Jack */
+    pub fn with_precedence(mut self, precedence: bool) -> Self {
+        self.precedence = precedence;
+        self
+    }
+
+    /** Parse the whole file, returning the (possibly partial) class along with every diagnostic found */
+    pub fn parse(&mut self) -> (Option<Class>, Vec<Diagnostic>) {
+        let class = match self.next_class() {
+            Ok(class) => {
+                // Read the rest of the file for the errors of the lexer, such as an unclosed comment
+                self.tokens.by_ref().for_each(drop);
+                Some(class)
             }
-            if let Ok(subroutine_type) = SubroutineType::from_keyword(&keyword) {
-                res.2.push(self.next_subroutine_dec(subroutine_type)?);
+            Err(e) => {
+                self.report(e);
+                None
             }
-            let closing_bracket = self.next_element().ok_or("} expected found eof")?;
-            self.pending_elements.push(closing_bracket.clone());
-            if closing_bracket == Symbol('}') {
-                break;
+        };
+        self.diagnostics
+            .append(&mut self.tokens.lexer_mut().diagnostics);
+        (class, std::mem::take(&mut self.diagnostics))
+    }
+
+    // Every construct left open at the end of the file expects something there, only the
+    // innermost one is reported
+    fn report(&mut self, diagnostic: Diagnostic) {
+        let reported = diagnostic.code == Code::UnexpectedEof
+            && self
+                .diagnostics
+                .iter()
+                .any(|x| x.code == Code::UnexpectedEof && x.span == diagnostic.span);
+        if !reported {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /** 'class' className '{' classVarDec* subroutineDec* '}' */
+    pub fn next_class(&mut self) -> Result<Class, Diagnostic> {
+        let start = self.tokens.expect(Terminal::Keyword(Keyword::Class))?;
+        let class_name = self.next_identifier("class name")?;
+
+        let mut res = Class(class_name, vec![], vec![], start);
+
+        self.tokens.expect(Symbol('{'))?;
+        while let Some(ele) = self.tokens.next_if(|x| x.0 != Symbol('}')) {
+            let span = ele.1;
+            let declaration = match ele.0 {
+                Terminal::Keyword(keyword) => {
+                    if let Ok(class_var_type) = ClassVarType::from_keyword(&keyword, span) {
+                        self.next_class_var_dec(class_var_type, span)
+                            .map(|x| res.1.push(x))
+                    } else if let Ok(subroutine_type) = SubroutineType::from_keyword(&keyword, span)
+                    {
+                        self.next_subroutine_dec(subroutine_type, span)
+                            .map(|x| res.2.push(x))
+                    } else {
+                        Err(ele.unexpected("class variable or subroutine declaration"))
+                    }
+                }
+                _ => Err(ele.unexpected("class variable or subroutine declaration")),
+            };
+            if let Err(e) = declaration {
+                self.report(e);
+                self.synchronize_declaration();
             }
         }
-        self.consume(Symbol('}'))?;
+        match self.tokens.expect(Symbol('}')) {
+            Ok(end) => res.3 = start.to(end),
+            Err(e) => self.report(e),
+        }
 
         Ok(res)
     }
 
     /** ('static'|'field') type varName (',' varName)* ';' */
-    fn next_class_var_dec(&mut self, class_var_type: ClassVarType) -> Result<ClassVarDec, String> {
-        let var_type = Type::from_terminal(self.next_element().ok_or("variable type expected found eof")?)?;
-        let mut res = ClassVarDec(class_var_type, var_type, vec![]);
+    fn next_class_var_dec(
+        &mut self,
+        class_var_type: ClassVarType,
+        start: Span,
+    ) -> Result<ClassVarDec, Diagnostic> {
+        let var_type = self.next_type("variable type")?;
+        let mut res = ClassVarDec(class_var_type, var_type, vec![], start);
         loop {
-            let var_name = self
-                .next_element()
-                .ok_or("variable identifier expected")?
-                .identifier()?;
+            let var_name = self.next_identifier("variable identifier")?;
             res.2.push(var_name);
-            let comma = self.next_element().ok_or(", or ; expected found eof")?;
-            if comma != Symbol(',') {
-                self.pending_elements.push(comma);
+            if self.tokens.next_if_eq(&Symbol(',')).is_none() {
                 break;
             }
         }
-        self.consume(Symbol(';'))?;
+        let end = self.tokens.expect(Symbol(';'))?;
+        res.3 = start.to(end);
         Ok(res)
     }
 
@@ -79,49 +137,45 @@ impl<'a> Parser<'a> {
     fn next_subroutine_dec(
         &mut self,
         subroutine_type: SubroutineType,
-    ) -> Result<SubroutineDec, String> {
-        let return_type =
-            ReturnType::from_terminal(self.next_element().ok_or("return type expected found eof")?)?;
-        let subroutine_name = self
-            .next_element()
-            .ok_or("subroutine name expected")?
-            .identifier()?;
-
-        self.consume(Symbol('('))?;
+        start: Span,
+    ) -> Result<SubroutineDec, Diagnostic> {
+        let return_type = ReturnType::from_token(self.tokens.expect_peek("return type")?.clone())?;
+        self.tokens.next();
+        let subroutine_name = self.next_identifier("subroutine name")?;
+
+        self.tokens.expect(Symbol('('))?;
         let parameter_list = self.next_parameter_list()?;
-        self.consume(Symbol(')'))?;
+        self.tokens.expect(Symbol(')'))?;
 
+        let subroutine_body = self.next_subroutine_body()?;
+        let end = subroutine_body.2;
         let res = SubroutineDec(
             subroutine_type,
             return_type,
             subroutine_name,
             parameter_list,
-            self.next_subroutine_body()?,
+            subroutine_body,
+            start.to(end),
         );
 
         Ok(res)
     }
 
     /** ((type varName) (',' typeVarName)* )? */
-    fn next_parameter_list(&mut self) -> Result<ParameterList, String> {
+    fn next_parameter_list(&mut self) -> Result<ParameterList, Diagnostic> {
         let mut res = vec![];
-        while let Some(next_element) = self.next_element() {
+        while let Some(next_element) = self.tokens.peek() {
             // check for empty parameter list
-            if Type::from_terminal(next_element.clone()).is_err() {
-                self.pending_elements.push(next_element);
+            let start = next_element.1;
+            let Ok(var_type) = Type::from_token(next_element.clone()) else {
                 return Ok(res);
-            }
+            };
+            self.tokens.next();
+            let var_name = self.next_identifier("variable name")?;
+            let end = var_name.1;
+            res.push(Parameter(var_type, var_name, start.to(end)));
 
-            let var_type = Type::from_terminal(next_element)?;
-            let var_name = self
-                .next_element()
-                .ok_or("variable name expected found eof")?
-                .identifier()?;
-            res.push(Parameter(var_type, var_name));
-
-            let comma = self.next_element().ok_or(", or ) expected found eof")?;
-            if comma != Symbol(',') {
-                self.pending_elements.push(comma);
+            if self.tokens.next_if_eq(&Symbol(',')).is_none() {
                 break;
             }
         }
@@ -129,189 +183,189 @@ impl<'a> Parser<'a> {
     }
 
     /** '{' varDec* statements '}' */
-    fn next_subroutine_body(&mut self) -> Result<SubroutineBody, String> {
-        self.consume(Symbol('{'))?;
-        let mut res = SubroutineBody(vec![], vec![]);
+    fn next_subroutine_body(&mut self) -> Result<SubroutineBody, Diagnostic> {
+        let start = self.tokens.expect(Symbol('{'))?;
+        let mut res = SubroutineBody(vec![], vec![], start);
         // Scan all the var declarations first
-        while let Some(ele) = self.next_element() {
-            if let Ok(Keyword::Var) = ele.clone().keyword() {
-                res.0.push(self.next_var_dec()?)
-            } else {
-                self.pending_elements.push(ele);
-                break;
+        while let Some(ele) = self.tokens.next_if_eq(&Terminal::Keyword(Keyword::Var)) {
+            match self.next_var_dec(ele.1) {
+                Ok(var_dec) => res.0.push(var_dec),
+                Err(e) => {
+                    self.report(e);
+                    self.synchronize_statement();
+                }
             }
         }
         // Then scan all the statements
-        while let Some(ele) = self.next_element() {
-            if let Ok(e) = ele.clone().keyword() {
-                res.1.push(self.next_statement(e)?);
-            } else {
-                self.pending_elements.push(ele);
-                break;
-            }
-        }
-        self.consume(Symbol('}'))?;
+        res.1 = self.next_statements();
+        let end = self.tokens.expect(Symbol('}'))?;
+        res.2 = start.to(end);
         Ok(res)
     }
 
     /** 'var' type varName (',' varName)* ';' */
-    fn next_var_dec(&mut self) -> Result<VarDec, String> {
-        let typ = Type::from_terminal(self.next_element().ok_or("variable type expected")?)?;
+    fn next_var_dec(&mut self, start: Span) -> Result<VarDec, Diagnostic> {
+        let typ = self.next_type("variable type")?;
         let mut vars = vec![];
         loop {
-            let var_name = self
-                .next_element()
-                .ok_or("variable name expected")?
-                .identifier()?;
+            let var_name = self.next_identifier("variable name")?;
             vars.push(var_name);
-            let comma = self.next_element().ok_or(", or ; expected")?;
-            if comma != Symbol(',') {
-                self.pending_elements.push(comma);
+            if self.tokens.next_if_eq(&Symbol(',')).is_none() {
                 break;
             }
         }
-        self.consume(Symbol(';'))?;
-        Ok(VarDec(typ, vars))
+        let end = self.tokens.expect(Symbol(';'))?;
+        Ok(VarDec(typ, vars, start.to(end)))
     }
 
     /** statement* */
-    fn next_statements(&mut self) -> Result<Statements, String> {
+    fn next_statements(&mut self) -> Statements {
         let mut res = vec![];
-        while let Some(keyword) = self.next_element() {
-            if keyword.clone().keyword().is_err() {
-                self.pending_elements.push(keyword);
-                break;
-            }
-            if let Ok(statement) = self.next_statement(keyword.keyword()?) {
-                res.push(statement);
-            } else {
-                break;
+        while let Some(element) = self.tokens.next_if(|x| x.0 != Symbol('}')) {
+            let statement = match element.0 {
+                Terminal::Keyword(kind) => self.next_statement(kind, element.1),
+                _ => Err(element.unexpected("statement")),
+            };
+            match statement {
+                Ok(statement) => res.push(statement),
+                Err(e) => {
+                    self.report(e);
+                    self.synchronize_statement();
+                }
             }
         }
-        Ok(res)
+        res
     }
 
     /** letStatement | ifStatement | whileStatement | doStatement | returnStatement */
-    fn next_statement(&mut self, kind: Keyword) -> Result<Statement, String> {
+    fn next_statement(&mut self, kind: Keyword, start: Span) -> Result<Statement, Diagnostic> {
         use Statement::*;
         let res = match kind {
-            Keyword::Let => LetStatement(self.next_let_statement()?),
-            Keyword::If => IfStatement(self.next_if_statement()?),
-            Keyword::While => WhileStatement(self.next_while_statement()?),
-            Keyword::Do => DoStatement(self.next_do_statement()?),
-            Keyword::Return => ReturnStatement(self.next_return_statement()?),
-            e => {
-                self.pending_elements.push(Terminal::Keyword(kind));
-                Err(format!("statement expected found: {e}"))?
-            }
+            Keyword::Let => LetStatement(self.next_let_statement(start)?),
+            Keyword::If => IfStatement(self.next_if_statement(start)?),
+            Keyword::While => WhileStatement(self.next_while_statement(start)?),
+            Keyword::Do => DoStatement(self.next_do_statement(start)?),
+            Keyword::Return => ReturnStatement(self.next_return_statement(start)?),
+            _ => Err(Token(Terminal::Keyword(kind), start).unexpected("statement"))?,
         };
         Ok(res)
     }
 
     /** 'let' varName ('[' expression ']')? '=' expression ';' */
-    fn next_let_statement(&mut self) -> Result<LetStatement, String> {
-        let var_name = self
-            .next_element()
-            .ok_or("variable name expected found eof")?
-            .identifier()?;
+    fn next_let_statement(&mut self, start: Span) -> Result<LetStatement, Diagnostic> {
+        let var_name = self.next_identifier("variable name")?;
         let mut index: Option<Expression> = None;
 
-        let opening_bracket = self.next_element().ok_or("[ or = expected found eof")?;
-        if opening_bracket == Symbol('[') {
+        if self.tokens.next_if_eq(&Symbol('[')).is_some() {
             let _ = index.insert(self.next_expression()?);
-            self.consume(Symbol(']'))?;
-        } else {
-            self.pending_elements.push(opening_bracket);
+            self.tokens.expect(Symbol(']'))?;
         }
 
-        self.consume(Symbol('='))?;
+        self.tokens.expect(Symbol('='))?;
         let expression = self.next_expression()?;
-        self.consume(Symbol(';'))?;
+        let end = self.tokens.expect(Symbol(';'))?;
 
-        Ok(LetStatement(var_name, index, expression))
+        Ok(LetStatement(var_name, index, expression, start.to(end)))
     }
 
-    fn next_if_statement(&mut self) -> Result<IfStatement, String> {
-        self.consume(Symbol('('))?;
+    fn next_if_statement(&mut self, start: Span) -> Result<IfStatement, Diagnostic> {
+        self.tokens.expect(Symbol('('))?;
         let expression = self.next_expression()?;
-        self.consume(Symbol(')'))?;
-        self.consume(Symbol('{'))?;
-        let statements = self.next_statements()?;
-        self.consume(Symbol('}'))?;
+        self.tokens.expect(Symbol(')'))?;
+        self.tokens.expect(Symbol('{'))?;
+        let statements = self.next_statements();
+        let mut end = self.tokens.expect(Symbol('}'))?;
         let mut else_statement: Option<Statements> = None;
-        if let Some(else_state) = self.next_element() {
-            if else_state == Terminal::Keyword(Keyword::Else) {
-                self.consume(Symbol('{'))?;
-                let _ = else_statement.insert(self.next_statements()?);
-                self.consume(Symbol('}'))?;
-            } else {
-                self.pending_elements.push(else_state);
-            }
+        if self
+            .tokens
+            .next_if_eq(&Terminal::Keyword(Keyword::Else))
+            .is_some()
+        {
+            self.tokens.expect(Symbol('{'))?;
+            let _ = else_statement.insert(self.next_statements());
+            end = self.tokens.expect(Symbol('}'))?;
         }
-        Ok(IfStatement(expression, statements, else_statement))
+        Ok(IfStatement(
+            expression,
+            statements,
+            else_statement,
+            start.to(end),
+        ))
     }
 
-    fn next_while_statement(&mut self) -> Result<WhileStatement, String> {
-        self.consume(Symbol('('))?;
+    fn next_while_statement(&mut self, start: Span) -> Result<WhileStatement, Diagnostic> {
+        self.tokens.expect(Symbol('('))?;
         let expression = self.next_expression()?;
-        self.consume(Symbol(')'))?;
-        self.consume(Symbol('{'))?;
-        let statements = self.next_statements()?;
-        self.consume(Symbol('}'))?;
-        Ok(WhileStatement(expression, statements))
-    }
-
-    fn next_do_statement(&mut self) -> Result<DoStatement, String> {
-        let caller_name = self
-            .next_element()
-            .ok_or("class or var or function name expected found eof")?
-            .identifier()?;
+        self.tokens.expect(Symbol(')'))?;
+        self.tokens.expect(Symbol('{'))?;
+        let statements = self.next_statements();
+        let end = self.tokens.expect(Symbol('}'))?;
+        Ok(WhileStatement(expression, statements, start.to(end)))
+    }
+
+    fn next_do_statement(&mut self, start: Span) -> Result<DoStatement, Diagnostic> {
+        let caller_name = self.next_identifier("class or var or function name")?;
         let subroutine_call = self.next_subroutine_call(caller_name)?;
-        self.consume(Symbol(';'))?;
-        Ok(DoStatement(subroutine_call))
+        let end = self.tokens.expect(Symbol(';'))?;
+        Ok(DoStatement(subroutine_call, start.to(end)))
     }
 
-    fn next_return_statement(&mut self) -> Result<ReturnStatement, String> {
-        let next_element = self.next_element().ok_or("; expected found eof")?;
-        if next_element == Symbol(';') {
-            Ok(ReturnStatement(None))
+    fn next_return_statement(&mut self, start: Span) -> Result<ReturnStatement, Diagnostic> {
+        if let Some(semicolon) = self.tokens.next_if_eq(&Symbol(';')) {
+            Ok(ReturnStatement(None, start.to(semicolon.1)))
         } else {
-            self.pending_elements.push(next_element);
             let expression = self.next_expression()?;
-            self.consume(Symbol(';'))?;
-            Ok(ReturnStatement(Some(expression)))
+            let end = self.tokens.expect(Symbol(';'))?;
+            Ok(ReturnStatement(Some(expression), start.to(end)))
         }
     }
 
-    fn next_expression_list(&mut self) -> Result<ExpressionList, String> {
+    fn next_expression_list(&mut self) -> Result<ExpressionList, Diagnostic> {
         let mut res = vec![];
-        while let Ok(expression) = self.next_expression() {
-            res.push(expression);
-            let comma = self.next_element().ok_or(", or ) expected found eof")?;
-            if comma != Symbol(',') {
-                self.pending_elements.push(comma);
-                break;
+        if self.tokens.next_is(&Symbol(')')) {
+            return Ok(res);
+        }
+        loop {
+            res.push(self.next_expression()?);
+            if self.tokens.next_if_eq(&Symbol(',')).is_none() {
+                return Ok(res);
             }
         }
-        Ok(res)
     }
 
-    fn next_expression(&mut self) -> Result<Expression, String> {
+    fn next_expression(&mut self) -> Result<Expression, Diagnostic> {
         let term = self.next_term()?;
-        let mut res = Expression(term, vec![]);
+        let span = term.span();
+        let mut res = Expression(term, vec![], span);
+
+        // The expression ends at the first token which is not an operator
+        while let Some((operation, start)) = self.next_operator() {
+            let term = self.next_term()?;
+            let op_span = start.to(term.span());
+            res.1.push(OpTerm(operation, term, op_span));
+            res.2 = res.2.to(op_span);
+        }
 
-        loop {
-            let next_term = self.next_element().ok_or("end of expression expected eof")?;
-            if let Err(e) = next_term.clone().symbol() {
-                self.pending_elements.push(next_term);
-                return Err(e);
-            }
-            let op = next_term.symbol()?;
-            if let Some(operation) = Op::from_char(op) {
-                let next_term = self.next_term()?;
-                res.1.push(OpTerm(operation, next_term));
-            } else {
-                self.pending_elements.push(Symbol(op));
+        if self.precedence {
+            return Ok(group_by_precedence(res.0, res.1, res.2));
+        }
+        // Report the first operator that would bind tighter than one before it
+        for (i, op_term) in res.1.iter().enumerate() {
+            let earlier = res.1[..i]
+                .iter()
+                .find(|x| x.0.precedence() < op_term.0.precedence());
+            if let Some(earlier) = earlier {
+                self.diagnostics.push(
+                    Diagnostic::warning(
+                        Code::MixedPrecedence,
+                        operator_span(op_term),
+                        format!("`{}` is evaluated after `{}`", op_term.0, earlier.0),
+                    )
+                    .with_label(format!("applies to the result of `{}`", earlier.0))
+                    .with_secondary(operator_span(earlier), "evaluated first")
+                    .with_note("Jack has no operator precedence, operators are evaluated from left to right")
+                    .with_note("add parentheses to make the order explicit, or compile with `--precedence`"),
+                );
                 break;
             }
         }
@@ -319,38 +373,63 @@ impl<'a> Parser<'a> {
         Ok(res)
     }
 
-    fn next_term(&mut self) -> Result<Term, String> {
-        let element = self.next_element().ok_or("term expected found eof")?;
+    // Operator continuing an expression, with its span
+    fn next_operator(&mut self) -> Option<(Op, Span)> {
+        let token = self.tokens.peek()?;
+        let operation = match token.0 {
+            Symbol(c) => Op::from_char(c)?,
+            _ => return None,
+        };
+        let span = token.1;
+        self.tokens.next();
+        Some((operation, span))
+    }
 
-        let res = match element {
+    // A token that cannot start a term is left for the recovery
+    fn next_term(&mut self) -> Result<Term, Diagnostic> {
+        let element = self.tokens.expect_peek("term")?.clone();
+        let start = element.1;
+
+        let res = match element.0 {
+            Symbol('(') => {
+                self.tokens.next();
+                let expression = self.next_expression()?;
+                let end = self.tokens.expect(Symbol(')'))?;
+                Term::BracketExpression(Box::new(expression), start.to(end))
+            }
             Symbol(c) => {
-                if c == '(' {
-                    let expression = self.next_expression()?;
-                    self.consume(Symbol(')'))?;
-                    Term::BracketExpression(Box::new(expression))
-                } else if let Some(un) = UnaryOp::from_char(c) {
-                    let next_term = self.next_term()?;
-                    Term::UnaryOpTerm(un, Box::new(next_term))
-                } else {
-                    self.pending_elements.push(element);
-                    Err(format!("term expected found symbol: {c}"))?
-                }
+                let un = UnaryOp::from_char(c).ok_or_else(|| element.unexpected("term"))?;
+                self.tokens.next();
+                let next_term = self.next_term()?;
+                let end = next_term.span();
+                Term::UnaryOpTerm(un, Box::new(next_term), start.to(end))
+            }
+            IntegerConstant(val) => {
+                self.tokens.next();
+                Term::IntegerConstant(val, start)
+            }
+            StringConstant(val) => {
+                self.tokens.next();
+                Term::StringConstant(val, start)
+            }
+            Terminal::Keyword(val) => {
+                let constant = KeywordConstant::from_keyword(val, start)?;
+                self.tokens.next();
+                Term::KeywordConstant(constant, start)
             }
-            IntegerConstant(val) => Term::IntegerConstant(val),
-            StringConstant(val) => Term::StringConstant(val),
-            Terminal::Keyword(val) => Term::KeywordConstant(KeywordConstant::from_keyword(val)?),
             Terminal::Identifier(val) => {
-                let square_bracket = self.next_element().ok_or("end of expression expected found eof")?;
-                if square_bracket == Symbol('[') {
-                    let index = self.next_expression()?;
-                    self.consume(Symbol(']'))?;
-                    return Ok(Term::VarNameIndex(val, Box::new(index)));
-                }
-                self.pending_elements.push(square_bracket);
-                if let Ok(subroutine_call) = self.next_subroutine_call(val.clone()) {
-                    Term::SubroutineCall(subroutine_call)
-                } else {
-                    Term::VarName(val)
+                self.tokens.next();
+                match self.tokens.peek().map(|x| &x.0) {
+                    Some(Symbol('[')) => {
+                        self.tokens.next();
+                        let index = self.next_expression()?;
+                        let end = self.tokens.expect(Symbol(']'))?;
+                        Term::VarNameIndex(val, Box::new(index), start.to(end))
+                    }
+                    Some(Symbol('(' | '.')) => {
+                        Term::SubroutineCall(self.next_subroutine_call(val)?)
+                    }
+                    _ => Term::VarName(val),
                 }
             }
         };
@@ -358,43 +437,322 @@ impl<'a> Parser<'a> {
         Ok(res)
     }
 
-    fn next_subroutine_call(&mut self, val: Identifier) -> Result<SubroutineCall, String> {
-        let dot_or_bracket = self.next_element().ok_or("end of expression expected found eof")?;
-        let res = if dot_or_bracket == Symbol('(') {
+    fn next_subroutine_call(&mut self, val: Identifier) -> Result<SubroutineCall, Diagnostic> {
+        let start = val.1;
+        let res = if self.tokens.next_if_eq(&Symbol('(')).is_some() {
             let expression_list = self.next_expression_list()?;
-            self.consume(Symbol(')'))?;
-            SubroutineCall(None, val, expression_list)
-        } else if dot_or_bracket == Symbol('.') {
-            let subroutine_name = self
-                .next_element()
-                .ok_or("subroutine name expected found eof")?
-                .identifier()?;
-            self.consume(Symbol('('))?;
+            let end = self.tokens.expect(Symbol(')'))?;
+            SubroutineCall(None, val, expression_list, start.to(end))
+        } else if self.tokens.next_if_eq(&Symbol('.')).is_some() {
+            let subroutine_name = self.next_identifier("subroutine name")?;
+            self.tokens.expect(Symbol('('))?;
             let expression_list = self.next_expression_list()?;
-            self.consume(Symbol(')'))?;
-            SubroutineCall(Some(val), subroutine_name, expression_list)
+            let end = self.tokens.expect(Symbol(')'))?;
+            SubroutineCall(Some(val), subroutine_name, expression_list, start.to(end))
         } else {
-            self.pending_elements.push(dot_or_bracket);
-            Err("expected subroutine call")?
+            Err(self
+                .tokens
+                .expect_peek("`(` or `.`")?
+                .unexpected("`(` or `.`"))?
+        };
+        Ok(res)
+    }
+
+    // The offending token is left in the stream so that the recovery can see it
+    fn next_identifier(&mut self, what: &str) -> Result<Identifier, Diagnostic> {
+        let element = self.tokens.expect_peek(what)?;
+        let Terminal::Identifier(val) = &element.0 else {
+            return Err(element.unexpected(what));
         };
+        let val = val.clone();
+        self.tokens.next();
+        Ok(val)
+    }
+
+    fn next_type(&mut self, what: &str) -> Result<Type, Diagnostic> {
+        let res = Type::from_token(self.tokens.expect_peek(what)?.clone())?;
+        self.tokens.next();
         Ok(res)
     }
 
-    fn consume(&mut self, term: Terminal) -> Result<(), String> {
-        let next_term = self
-            .next_element()
-            .ok_or(format!("expected {term} found EOF"))?;
-        if next_term != term {
-            self.pending_elements.push(next_term.clone());
-            Err(format!("expected {term} found {next_term}"))?
+    // Skip tokens until the start of the next statement, after a `;` or before a `}`
+    fn synchronize_statement(&mut self) {
+        let mut depth = 0;
+        while let Some(element) = self.tokens.peek() {
+            match element.0 {
+                Symbol('{') => depth += 1,
+                Symbol('}') if depth == 0 => return,
+                Symbol('}') => {
+                    depth -= 1;
+                    // A block closing at the top level ends the statement unless an else follows
+                    let else_follows = self
+                        .tokens
+                        .peek_nth(1)
+                        .is_some_and(|x| x.0 == Terminal::Keyword(Keyword::Else));
+                    if depth == 0 && !else_follows {
+                        self.tokens.next();
+                        return;
+                    }
+                }
+                Symbol(';') if depth == 0 => {
+                    self.tokens.next();
+                    return;
+                }
+                Terminal::Keyword(
+                    Keyword::Let | Keyword::Do | Keyword::If | Keyword::While | Keyword::Return,
+                ) if depth == 0 => return,
+                _ => {}
+            }
+            self.tokens.next();
+        }
+    }
+
+    // Skip tokens until the start of the next class variable or subroutine declaration
+    fn synchronize_declaration(&mut self) {
+        let mut depth = 0;
+        while let Some(element) = self.tokens.peek() {
+            match element.0 {
+                Symbol('{') => depth += 1,
+                Symbol('}') if depth == 0 => {
+                    // Either the end of the class or the end of a broken subroutine
+                    let is_class_end = self
+                        .tokens
+                        .peek_nth(1)
+                        .is_none_or(|x| !is_declaration_start(&x.0));
+                    if !is_class_end {
+                        self.tokens.next();
+                    }
+                    return;
+                }
+                Symbol('}') => depth -= 1,
+                ref terminal if depth == 0 && is_declaration_start(terminal) => return,
+                _ => {}
+            }
+            self.tokens.next();
+        }
+    }
+}
+
+fn is_declaration_start(terminal: &Terminal) -> bool {
+    matches!(
+        terminal,
+        Terminal::Keyword(
+            Keyword::Static
+                | Keyword::Field
+                | Keyword::Constructor
+                | Keyword::Function
+                | Keyword::Method
+        )
+    )
+}
+
+// Span of the operator alone, the span of an OpTerm starts with it
+fn operator_span(op_term: &OpTerm) -> Span {
+    let mut res = op_term.2;
+    res.end = res.start;
+    res.end.offset += 1;
+    res.end.column += 1;
+    res
+}
+
+// Split the chain at its loosest operators, the parts in between become bracketed expressions
+fn group_by_precedence(first: Term, rest: Vec<OpTerm>, span: Span) -> Expression {
+    let Some(loosest) = rest.iter().map(|x| x.0.precedence()).min() else {
+        return Expression(first, rest, span);
+    };
+    if rest.iter().all(|x| x.0.precedence() == loosest) {
+        return Expression(first, rest, span);
+    }
+    // Each loose operator is followed by its term and the tighter operators up to the next one
+    let mut first = (first, vec![]);
+    let mut parts: Vec<(OpTerm, Vec<OpTerm>)> = vec![];
+    for op_term in rest {
+        if op_term.0.precedence() == loosest {
+            parts.push((op_term, vec![]));
+        } else if let Some(part) = parts.last_mut() {
+            part.1.push(op_term);
+        } else {
+            first.1.push(op_term);
         }
-        Ok(())
     }
+    let first = bracket(first.0, first.1);
+    let rest = parts
+        .into_iter()
+        .map(|(op_term, tighter)| {
+            let term = bracket(op_term.1, tighter);
+            let span = op_term.2.to(term.span());
+            OpTerm(op_term.0, term, span)
+        })
+        .collect();
+    Expression(first, rest, span)
+}
 
-    fn next_element(&mut self) -> Option<Terminal> {
-        if self.pending_elements.is_empty() {
-            return self.lexer.next_element();
+// The group has the same span as its expression, a written bracket also covers the `(` and `)`
+fn bracket(term: Term, rest: Vec<OpTerm>) -> Term {
+    match rest.last() {
+        Some(last) => {
+            let span = term.span().to(last.2);
+            Term::BracketExpression(Box::new(group_by_precedence(term, rest, span)), span)
         }
-        self.pending_elements.pop()
+        None => term,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Parser;
+    use crate::diagnostic::Code;
+    use crate::grammar::expression::{Expression, Term};
+    use crate::grammar::statement::Statement;
+    use crate::grammar::structure::Class;
+
+    // The class and the codes of the diagnostics, with their lines
+    fn parse(source: &str) -> (Option<Class>, Vec<(Code, usize)>) {
+        let content = source.chars().collect::<Vec<_>>();
+        let (class, diagnostics) = Parser::new(&content, 0).parse();
+        let diagnostics = diagnostics
+            .iter()
+            .map(|x| (x.code, x.span.start.line))
+            .collect();
+        (class, diagnostics)
+    }
+
+    #[test]
+    fn statements_after_an_error() {
+        let (class, diagnostics) = parse(
+            "class A {
+                function void f() {
+                    let x = ;
+                    do g(;
+                    let y = 1;
+                    return;
+                }
+            }",
+        );
+        assert_eq!(
+            diagnostics,
+            [(Code::UnexpectedToken, 3), (Code::UnexpectedToken, 4)]
+        );
+        assert_eq!(class.unwrap().2[0].4 .1.len(), 2);
+    }
+
+    #[test]
+    fn errors_in_blocks() {
+        let (class, diagnostics) = parse(
+            "class A {
+                function void f() {
+                    if (x) { let = 1; } else { let y = 2 }
+                    while (x { do f(); }
+                    return;
+                }
+            }",
+        );
+        assert_eq!(
+            diagnostics,
+            [
+                (Code::UnexpectedToken, 3),
+                (Code::UnexpectedToken, 3),
+                (Code::UnexpectedToken, 4)
+            ]
+        );
+        // The return is read as a statement of f
+        assert_eq!(class.unwrap().2[0].4 .1.len(), 2);
+    }
+
+    #[test]
+    fn declarations_after_an_error() {
+        let (class, diagnostics) = parse(
+            "class A {
+                field int ;
+                var int x;
+                function void f( { return; }
+                method void g() { return; }
+            }",
+        );
+        // Everything up to the next declaration is skipped, the misplaced var included
+        assert_eq!(
+            diagnostics,
+            [(Code::UnexpectedToken, 2), (Code::UnexpectedToken, 4)]
+        );
+        let class = class.unwrap();
+        assert_eq!(class.2.len(), 1);
+        assert_eq!(class.2[0].2 .0, "g");
+    }
+
+    #[test]
+    fn end_of_file() {
+        let (class, diagnostics) = parse("class A {\n function void f() {\n return;");
+        assert!(class.is_some());
+        assert_eq!(diagnostics, [(Code::UnexpectedEof, 3)]);
+        let (class, diagnostics) = parse("class");
+        assert!(class.is_none());
+        assert_eq!(diagnostics, [(Code::UnexpectedEof, 1)]);
+        // The call, the body and the class are all open at the end, only the call is reported
+        let (class, diagnostics) =
+            parse("class A { function void f() { do Output.printString(\"ab");
+        assert!(class.is_some());
+        assert_eq!(
+            diagnostics,
+            [(Code::UnexpectedEof, 1), (Code::UnterminatedString, 1)]
+        );
+    }
+
+    // The expression of `let x = ...` with its groups in brackets, and the diagnostic codes
+    fn group(expression: &str, precedence: bool) -> (String, Vec<Code>) {
+        fn show(expression: &Expression) -> String {
+            let mut res = show_term(&expression.0);
+            for op_term in &expression.1 {
+                res += &format!(" {} {}", op_term.0, show_term(&op_term.1));
+            }
+            res
+        }
+        fn show_term(term: &Term) -> String {
+            match term {
+                Term::VarName(var) => var.0.clone(),
+                Term::IntegerConstant(value, _) => value.to_string(),
+                Term::BracketExpression(exp, _) => format!("({})", show(exp)),
+                _ => unimplemented!(),
+            }
+        }
+        let source = format!("class A {{ function void f() {{ let x = {expression}; }} }}");
+        let content = source.chars().collect::<Vec<_>>();
+        let (class, diagnostics) = Parser::new(&content, 0).with_precedence(precedence).parse();
+        let Statement::LetStatement(s) = &class.unwrap().2[0].4 .1[0] else {
+            unreachable!()
+        };
+        (show(&s.2), diagnostics.iter().map(|x| x.code).collect())
+    }
+
+    #[test]
+    fn precedence() {
+        let grouped = |expression| group(expression, true).0;
+        assert_eq!(grouped("a + b * c"), "a + (b * c)");
+        assert_eq!(grouped("a * b + c"), "(a * b) + c");
+        assert_eq!(grouped("a - b + c"), "a - b + c");
+        assert_eq!(grouped("a * b / c - d"), "(a * b / c) - d");
+        assert_eq!(grouped("a < b + 1 & c = 2"), "(a < (b + 1)) & (c = 2)");
+        assert_eq!(
+            grouped("a | b & c < d + e * f"),
+            "a | (b & (c < (d + (e * f))))"
+        );
+        // Written brackets are kept as they are
+        assert_eq!(grouped("(a + b) * c"), "(a + b) * c");
+        // Without the option the operators stay in source order
+        assert_eq!(group("a + b * c", false).0, "a + b * c");
+    }
+
+    #[test]
+    fn mixed_precedence_warning() {
+        let codes = |expression| group(expression, false).1;
+        assert_eq!(codes("a + b * c"), [Code::MixedPrecedence]);
+        assert_eq!(codes("a | b & c + d"), [Code::MixedPrecedence]);
+        assert_eq!(codes("a = b + c"), [Code::MixedPrecedence]);
+        // Chains that are the same either way are fine
+        assert!(codes("a * b + c").is_empty());
+        assert!(codes("a - b + c").is_empty());
+        assert!(codes("a + (b * c)").is_empty());
+        // With the option the expression is grouped instead
+        assert!(group("a + b * c", true).1.is_empty());
+        assert!(group("a | b & c + d", true).1.is_empty());
     }
 }
@@ -0,0 +1,66 @@
+use crate::span::FileId;
+use std::path::PathBuf;
+
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub content: Vec<char>,
+    /**offset of the first character of each line*/
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(path: PathBuf, content: Vec<char>) -> Self {
+        let mut line_starts = vec![0];
+        for (i, &c) in content.iter().enumerate() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        Self {
+            path,
+            content,
+            line_starts,
+        }
+    }
+
+    /** Offset of the first character of the given 1-based line */
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.line_starts.get(line.checked_sub(1)?).copied()
+    }
+
+    // Text of the given 1-based line without the line terminator
+    pub fn line(&self, line: usize) -> Option<String> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.content.len(), |&x| x - 1);
+        Some(
+            self.content[start..end]
+                .iter()
+                .filter(|&&c| c != '\r')
+                .collect(),
+        )
+    }
+}
+
+/** All the files taking part in a compilation, indexed by FileId */
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, path: PathBuf, content: Vec<char>) -> FileId {
+        self.files.push(SourceFile::new(path, content));
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file)
+    }
+}
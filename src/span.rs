@@ -0,0 +1,53 @@
+use std::fmt::{Display, Formatter};
+
+/** Index of a source file in the list of files being compiled */
+pub type FileId = usize;
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Position {
+    /**offset in characters from the start of the file*/
+    pub offset: usize,
+    /**1-based line number*/
+    pub line: usize,
+    /**1-based column number*/
+    pub column: usize,
+}
+
+impl Position {
+    pub fn start() -> Self {
+        Self {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+/** A range of source text, `end` is exclusive */
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(file: FileId, start: Position, end: Position) -> Self {
+        Self { file, start, end }
+    }
+
+    // Span covering both self and other
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            file: self.file,
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.start.line, self.start.column)
+    }
+}
@@ -1,4 +1,8 @@
-use crate::grammar::structure::Type;
+use crate::diagnostic::{Code, Diagnostic};
+use crate::grammar::structure::{Class, ClassVarType, SubroutineDec, SubroutineType, Type};
+use crate::grammar::terminal::Identifier;
+use crate::span::Span;
+use crate::vm::Segment;
 use std::collections::HashMap;
 
 pub struct SymbolTable {
@@ -11,22 +15,77 @@ impl SymbolTable {
             table: HashMap::new(),
         }
     }
+    /** Table of the statics and fields of a class */
+    pub fn for_class(class: &Class) -> Self {
+        let mut res = Self::new();
+        for var_dec in &class.1 {
+            let kind = match var_dec.0 {
+                ClassVarType::Static => Kind::Static,
+                ClassVarType::Field => Kind::Field,
+            };
+            for var in &var_dec.2 {
+                // Duplicates are reported by the compiler
+                res.define(var, var_dec.1.clone(), kind).ok();
+            }
+        }
+        res
+    }
+
+    /** Table of the arguments and local variables of a subroutine */
+    pub fn for_subroutine(class: &Class, subroutine_dec: &SubroutineDec) -> Self {
+        let mut res = Self::new();
+        if let SubroutineType::Method = subroutine_dec.0 {
+            let this = Identifier("this".into(), Span::default());
+            res.define(&this, Type::ClassName(class.0.clone()), Kind::Arg)
+                .ok();
+        }
+        for param in &subroutine_dec.3 {
+            res.define(&param.1, param.0.clone(), Kind::Arg).ok();
+        }
+        for var_dec in &subroutine_dec.4 .0 {
+            for var in &var_dec.1 {
+                res.define(var, var_dec.0.clone(), Kind::Var).ok();
+            }
+        }
+        res
+    }
+
     pub fn reset(&mut self) {
         self.table.clear();
     }
 
-    pub fn define(&mut self, name: String, variable_type: Type, kind: Kind) -> bool {
-        let index = self.var_count(kind);
-        self.table
-            .insert(
-                name,
-                Variable {
-                    variable_type,
-                    kind,
-                    index,
-                },
+    /** Add a variable, a name that is already defined is kept as it is and reported */
+    pub fn define(
+        &mut self,
+        name: &Identifier,
+        variable_type: Type,
+        kind: Kind,
+    ) -> Result<(), Diagnostic> {
+        if let Some(previous) = self.table.get(&name.0) {
+            let mut err = Diagnostic::error(
+                Code::DuplicateDefinition,
+                name.1,
+                format!("`{}` is defined multiple times", name.0),
             )
-            .is_some()
+            .with_label(format!("`{}` redefined here", name.0));
+            if previous.span != Span::default() {
+                err = err.with_secondary(previous.span, "first defined here");
+            }
+            return Err(err);
+        }
+        let index = self.var_count(kind);
+        self.table.insert(
+            name.0.clone(),
+            Variable {
+                variable_type,
+                kind,
+                index,
+                span: name.1,
+                reads: 0,
+                writes: 0,
+            },
+        );
+        Ok(())
     }
 
     pub fn var_count(&self, kind: Kind) -> usize {
@@ -45,28 +104,65 @@ impl SymbolTable {
         Some(self.table.get(name)?.index)
     }
 
-    pub fn mapping_of(&self, name: &String) -> Result<String, String> {
-        let kind = self
-            .kind_of(name)
-            .ok_or(format!("undefined variable: {name}"))?;
-        let index = self
-            .index_of(name)
-            .ok_or(format!("undefined variable: {name}"))?;
-        let res = match kind {
-            Kind::Field => format!("this {index}"),
-            Kind::Static => format!("static {index}"),
-            Kind::Arg => format!("argument {index}"),
-            Kind::Var => format!("local {index}"),
+    pub fn span_of(&self, name: &String) -> Option<Span> {
+        Some(self.table.get(name)?.span)
+    }
+
+    /** Count a use of the variable, returns whether it is defined in this table */
+    pub fn record_read(&mut self, name: &String) -> bool {
+        self.table.get_mut(name).map(|x| x.reads += 1).is_some()
+    }
+
+    /** Count an assignment to the variable, returns whether it is defined in this table */
+    pub fn record_write(&mut self, name: &String) -> bool {
+        self.table.get_mut(name).map(|x| x.writes += 1).is_some()
+    }
+
+    pub fn variables(&self) -> impl Iterator<Item = (&String, &Variable)> {
+        self.table.iter()
+    }
+
+    /** Names of the variables that can be written in the source */
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.table
+            .keys()
+            .map(|x| x.as_str())
+            .filter(|x| *x != "this")
+    }
+
+    /** Segment and index of a variable in the VM */
+    pub fn mapping_of(&self, name: &Identifier) -> Result<(Segment, u16), Diagnostic> {
+        let undefined = || {
+            Diagnostic::error(
+                Code::UndefinedVariable,
+                name.1,
+                format!("undefined variable `{}`", name.0),
+            )
+            .with_label("not found in this scope")
+        };
+        let kind = self.kind_of(&name.0).ok_or_else(undefined)?;
+        let index = self.index_of(&name.0).ok_or_else(undefined)?;
+        let segment = match kind {
+            Kind::Field => Segment::This,
+            Kind::Static => Segment::Static,
+            Kind::Arg => Segment::Argument,
+            Kind::Var => Segment::Local,
         };
-        Ok(res)
+        Ok((segment, index as u16))
     }
 }
 
 #[derive(Hash, Eq, PartialEq)]
 pub struct Variable {
-    variable_type: Type,
-    kind: Kind,
-    index: usize,
+    pub variable_type: Type,
+    pub kind: Kind,
+    pub index: usize,
+    /**location of the declaration*/
+    pub span: Span,
+    /**number of times the value is used*/
+    pub reads: usize,
+    /**number of times a value is assigned*/
+    pub writes: usize,
 }
 
 #[derive(Hash, Eq, PartialEq, Copy, Clone)]
@@ -76,3 +172,45 @@ pub enum Kind {
     Arg,
     Var,
 }
+
+/** The candidate closest to name, if it is close enough to be a likely typo */
+pub fn similar_name<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    // A one letter name is one edit away from any other one letter name
+    let max_distance = (name.len() / 3).max(1);
+    candidates
+        .map(|x| (edit_distance(name, x), x))
+        .filter(|(distance, _)| *distance <= max_distance && *distance < name.len())
+        .min()
+        .map(|(_, x)| x)
+}
+
+// Levenshtein distance keeping a single row of the table
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, x) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(x != *y);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::similar_name;
+
+    #[test]
+    fn suggestions() {
+        let names = ["a", "ab", "counter", "index"];
+        assert_eq!(similar_name("countr", names.into_iter()), Some("counter"));
+        assert_eq!(similar_name("indx", names.into_iter()), Some("index"));
+        assert_eq!(similar_name("ac", names.into_iter()), Some("a"));
+        assert_eq!(similar_name("y", names.into_iter()), None);
+        assert_eq!(similar_name("total", names.into_iter()), None);
+    }
+}
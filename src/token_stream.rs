@@ -0,0 +1,97 @@
+use crate::diagnostic::{Code, Diagnostic};
+use crate::grammar::terminal::{Terminal, Token};
+use crate::lexer::Lexer;
+use crate::span::Span;
+use std::collections::VecDeque;
+
+/** Tokens of a file, with as much lookahead as needed */
+pub struct TokenStream<'a> {
+    lexer: Lexer<'a>,
+    /**tokens read from the lexer but not returned yet*/
+    buffer: VecDeque<Token>,
+}
+
+impl<'a> TokenStream<'a> {
+    pub fn new(lexer: Lexer<'a>) -> Self {
+        Self {
+            lexer,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    pub fn lexer_mut(&mut self) -> &mut Lexer<'a> {
+        &mut self.lexer
+    }
+
+    // Read from the lexer until n tokens are waiting or the file ends
+    fn fill(&mut self, n: usize) {
+        while self.buffer.len() < n {
+            let Some(token) = self.lexer.next() else {
+                return;
+            };
+            self.buffer.push_back(token);
+        }
+    }
+
+    /** Next token without consuming it */
+    pub fn peek(&mut self) -> Option<&Token> {
+        self.peek_nth(0)
+    }
+
+    /** Token n positions ahead without consuming anything, `peek_nth(0)` being the next one */
+    pub fn peek_nth(&mut self, n: usize) -> Option<&Token> {
+        self.fill(n + 1);
+        self.buffer.get(n)
+    }
+
+    pub fn next_is(&mut self, terminal: &Terminal) -> bool {
+        self.peek().is_some_and(|x| &x.0 == terminal)
+    }
+
+    /** Consume the next token if it satisfies the predicate */
+    pub fn next_if(&mut self, predicate: impl FnOnce(&Token) -> bool) -> Option<Token> {
+        if predicate(self.peek()?) {
+            self.next()
+        } else {
+            None
+        }
+    }
+
+    pub fn next_if_eq(&mut self, terminal: &Terminal) -> Option<Token> {
+        self.next_if(|x| &x.0 == terminal)
+    }
+
+    /** Next token without consuming it, or an error pointing to the end of the file */
+    pub fn expect_peek(&mut self, what: &str) -> Result<&Token, Diagnostic> {
+        self.fill(1);
+        match self.buffer.front() {
+            Some(token) => Ok(token),
+            None => Err(Diagnostic::error(
+                Code::UnexpectedEof,
+                self.lexer.eof_span(),
+                format!("expected {what}, found end of file"),
+            )
+            .with_label(format!("expected {what}"))),
+        }
+    }
+
+    /** Consume the given terminal, any other token is left in the stream */
+    pub fn expect(&mut self, terminal: Terminal) -> Result<Span, Diagnostic> {
+        let what = terminal.description();
+        let token = self.expect_peek(&what)?;
+        if token.0 != terminal {
+            return Err(token.unexpected(&what));
+        }
+        let span = token.1;
+        self.next();
+        Ok(span)
+    }
+}
+
+impl Iterator for TokenStream<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.buffer.pop_front().or_else(|| self.lexer.next())
+    }
+}
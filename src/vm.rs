@@ -0,0 +1,328 @@
+use crate::backend::{source_lines, SourceLine};
+use crate::diagnostic::{Code, Diagnostic};
+use crate::source::SourceFile;
+use crate::span::FileId;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/** Memory segments of the VM */
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Segment {
+    Constant,
+    Local,
+    Argument,
+    This,
+    That,
+    Static,
+    Temp,
+    Pointer,
+}
+
+/** Arithmetic and logical commands, operating on the top of the stack */
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ArithmeticOp {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+/** A command of the VM language, labels are local to the enclosing function */
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum VmCommand {
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Arithmetic(ArithmeticOp),
+    Label(String),
+    Goto(String),
+    IfGoto(String),
+    /**name and number of local variables*/
+    Function(String, u16),
+    /**name and number of arguments*/
+    Call(String, u16),
+    Return,
+}
+
+impl Segment {
+    /** Number of slots of the segments mapped to fixed addresses */
+    pub fn size(&self) -> Option<u16> {
+        match self {
+            Segment::Temp => Some(8),
+            Segment::Pointer => Some(2),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Segment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Segment::Constant => "constant",
+            Segment::Local => "local",
+            Segment::Argument => "argument",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Static => "static",
+            Segment::Temp => "temp",
+            Segment::Pointer => "pointer",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Segment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "constant" => Segment::Constant,
+            "local" => Segment::Local,
+            "argument" => Segment::Argument,
+            "this" => Segment::This,
+            "that" => Segment::That,
+            "static" => Segment::Static,
+            "temp" => Segment::Temp,
+            "pointer" => Segment::Pointer,
+            _ => Err(format!("unknown segment `{s}`"))?,
+        })
+    }
+}
+
+impl Display for ArithmeticOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ArithmeticOp::Add => "add",
+            ArithmeticOp::Sub => "sub",
+            ArithmeticOp::Neg => "neg",
+            ArithmeticOp::Eq => "eq",
+            ArithmeticOp::Gt => "gt",
+            ArithmeticOp::Lt => "lt",
+            ArithmeticOp::And => "and",
+            ArithmeticOp::Or => "or",
+            ArithmeticOp::Not => "not",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for VmCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmCommand::Push(segment, index) => write!(f, "push {segment} {index}"),
+            VmCommand::Pop(segment, index) => write!(f, "pop {segment} {index}"),
+            VmCommand::Arithmetic(op) => write!(f, "{op}"),
+            VmCommand::Label(label) => write!(f, "label {label}"),
+            VmCommand::Goto(label) => write!(f, "goto {label}"),
+            VmCommand::IfGoto(label) => write!(f, "if-goto {label}"),
+            VmCommand::Function(name, locals) => write!(f, "function {name} {locals}"),
+            VmCommand::Call(name, args) => write!(f, "call {name} {args}"),
+            VmCommand::Return => write!(f, "return"),
+        }
+    }
+}
+
+impl FromStr for VmCommand {
+    type Err = String;
+
+    /** Parse a single command, without comments */
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        let Some(&command) = words.first() else {
+            Err("expected a VM command")?
+        };
+        let op = arithmetic_op(command);
+        let operands = match command {
+            "push" | "pop" | "function" | "call" => 2,
+            "label" | "goto" | "if-goto" => 1,
+            "return" => 0,
+            _ if op.is_some() => 0,
+            _ => Err(format!("unknown VM command `{command}`"))?,
+        };
+        if words.len() != operands + 1 {
+            Err(format!(
+                "`{command}` takes {operands} operand{}, found {}",
+                if operands == 1 { "" } else { "s" },
+                words.len() - 1
+            ))?
+        }
+        let label = || words[1].to_string();
+        let res = match command {
+            "push" | "pop" => {
+                let segment = words[1].parse::<Segment>()?;
+                let index = number(words[2])?;
+                if let Some(size) = segment.size().filter(|&x| index >= x) {
+                    Err(format!(
+                        "index {index} is out of range for segment `{segment}` of size {size}"
+                    ))?
+                }
+                if command == "push" {
+                    VmCommand::Push(segment, index)
+                } else if segment == Segment::Constant {
+                    Err("cannot pop into the constant segment")?
+                } else {
+                    VmCommand::Pop(segment, index)
+                }
+            }
+            "label" => VmCommand::Label(label()),
+            "goto" => VmCommand::Goto(label()),
+            "if-goto" => VmCommand::IfGoto(label()),
+            "function" => VmCommand::Function(label(), number(words[2])?),
+            "call" => VmCommand::Call(label(), number(words[2])?),
+            "return" => VmCommand::Return,
+            _ => VmCommand::Arithmetic(op.unwrap()),
+        };
+        Ok(res)
+    }
+}
+
+fn arithmetic_op(command: &str) -> Option<ArithmeticOp> {
+    Some(match command {
+        "add" => ArithmeticOp::Add,
+        "sub" => ArithmeticOp::Sub,
+        "neg" => ArithmeticOp::Neg,
+        "eq" => ArithmeticOp::Eq,
+        "gt" => ArithmeticOp::Gt,
+        "lt" => ArithmeticOp::Lt,
+        "and" => ArithmeticOp::And,
+        "or" => ArithmeticOp::Or,
+        "not" => ArithmeticOp::Not,
+        _ => None?,
+    })
+}
+
+fn number(word: &str) -> Result<u16, String> {
+    word.parse()
+        .ok()
+        .filter(|&x| x <= 32767)
+        .ok_or_else(|| format!("expected a number between 0 and 32767, found `{word}`"))
+}
+
+/** The shortest commands pushing a value, constants of the VM are not negative */
+pub fn push_constant(value: i16) -> Vec<VmCommand> {
+    match value {
+        0.. => vec![VmCommand::Push(Segment::Constant, value as u16)],
+        i16::MIN => vec![
+            VmCommand::Push(Segment::Constant, i16::MAX as u16),
+            VmCommand::Arithmetic(ArithmeticOp::Not),
+        ],
+        _ => vec![
+            VmCommand::Push(Segment::Constant, value.unsigned_abs()),
+            VmCommand::Arithmetic(ArithmeticOp::Neg),
+        ],
+    }
+}
+
+/** Parse a .vm file, reporting the first invalid command */
+pub fn parse(source: &SourceFile, file: FileId) -> Result<Vec<VmCommand>, Diagnostic> {
+    source_lines(source, file)
+        .iter()
+        .map(|line| line.text.parse().map_err(|e| invalid(line, e)))
+        .collect()
+}
+
+/** The text of a .vm file, with the body of each function indented */
+pub fn to_text(commands: &[VmCommand]) -> String {
+    let mut res = String::new();
+    for command in commands {
+        if !matches!(command, VmCommand::Function(..)) {
+            res.push_str("  ");
+        }
+        res.push_str(&format!("{command}\n"));
+    }
+    res
+}
+
+fn invalid(line: &SourceLine, message: String) -> Diagnostic {
+    Diagnostic::error(Code::InvalidVmCommand, line.span, message).with_label("invalid VM command")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, push_constant, to_text, ArithmeticOp, Segment, VmCommand};
+    use crate::source::SourceMap;
+
+    #[test]
+    fn display_and_parse_round_trip() {
+        let commands = [
+            VmCommand::Push(Segment::Constant, 32767),
+            VmCommand::Pop(Segment::Temp, 7),
+            VmCommand::Push(Segment::Pointer, 1),
+            VmCommand::Arithmetic(ArithmeticOp::Not),
+            VmCommand::Label("WHILE_LABEL$1".into()),
+            VmCommand::Goto("L".into()),
+            VmCommand::IfGoto("L".into()),
+            VmCommand::Function("Main.main".into(), 2),
+            VmCommand::Call("Math.multiply".into(), 2),
+            VmCommand::Return,
+        ];
+        let text = commands.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(text[1], "pop temp 7");
+        assert_eq!(text[4], "label WHILE_LABEL$1");
+        assert_eq!(text[6], "if-goto L");
+        for (command, text) in commands.iter().zip(&text) {
+            assert_eq!(&text.parse::<VmCommand>().unwrap(), command);
+        }
+        assert_eq!(
+            "  call   Sys.wait\t1 ".parse(),
+            Ok(VmCommand::Call("Sys.wait".into(), 1))
+        );
+    }
+
+    #[test]
+    fn invalid_commands() {
+        let error = |text: &str| text.parse::<VmCommand>().unwrap_err();
+        assert_eq!(error(""), "expected a VM command");
+        assert_eq!(error("jump L"), "unknown VM command `jump`");
+        assert_eq!(error("push local"), "`push` takes 2 operands, found 1");
+        assert_eq!(error("add 1"), "`add` takes 0 operands, found 1");
+        assert_eq!(error("push heap 0"), "unknown segment `heap`");
+        assert_eq!(
+            error("pop constant 1"),
+            "cannot pop into the constant segment"
+        );
+        assert_eq!(
+            error("push temp 8"),
+            "index 8 is out of range for segment `temp` of size 8"
+        );
+        assert_eq!(
+            error("push constant 32768"),
+            "expected a number between 0 and 32767, found `32768`"
+        );
+    }
+
+    #[test]
+    fn file() {
+        let mut sources = SourceMap::new();
+        let text = "function F.f 0 // comment\n\n  push constant 1\n  return\n";
+        let file = sources.add("F.vm".into(), text.chars().collect());
+        let commands = parse(sources.get(file).unwrap(), file).unwrap();
+        assert_eq!(
+            to_text(&commands),
+            "function F.f 0\n  push constant 1\n  return\n"
+        );
+
+        let file = sources.add(
+            "G.vm".into(),
+            "push constant 1\npop that\n".chars().collect(),
+        );
+        let error = parse(sources.get(file).unwrap(), file).unwrap_err();
+        assert_eq!(error.span.start.line, 2);
+    }
+
+    #[test]
+    fn constants() {
+        let push = |x| VmCommand::Push(Segment::Constant, x);
+        let op = VmCommand::Arithmetic;
+        assert_eq!(push_constant(5), [push(5)]);
+        assert_eq!(push_constant(-5), [push(5), op(ArithmeticOp::Neg)]);
+        assert_eq!(
+            push_constant(i16::MIN),
+            [push(32767), op(ArithmeticOp::Not)]
+        );
+    }
+}
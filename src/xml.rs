@@ -0,0 +1,332 @@
+use crate::grammar::expression::{Expression, KeywordConstant, SubroutineCall, Term, UnaryOp};
+use crate::grammar::statement::{Statement, Statements};
+use crate::grammar::structure::{
+    Class, ClassVarDec, ClassVarType, ParameterList, ReturnType, SubroutineBody, SubroutineDec,
+    SubroutineType, Type, VarDec,
+};
+use crate::grammar::terminal::{Identifier, Keyword, Terminal, Token};
+use std::fmt::Display;
+
+/** Tokens in the format of the nand2tetris xxxT.xml files */
+pub fn tokens(tokens: &[Token]) -> String {
+    let mut res = String::from("<tokens>\n");
+    for token in tokens {
+        res.push_str(&element(tag_of(&token.0), &token.0));
+        res.push('\n');
+    }
+    res.push_str("</tokens>\n");
+    res
+}
+
+/** Parse tree of a class in the format of the nand2tetris xxx.xml files */
+pub fn class(class: &Class) -> String {
+    let mut writer = Writer::default();
+    writer.class(class);
+    writer.res
+}
+
+fn tag_of(terminal: &Terminal) -> &'static str {
+    match terminal {
+        Terminal::Keyword(_) => "keyword",
+        Terminal::Symbol(_) => "symbol",
+        Terminal::IntegerConstant(_) => "integerConstant",
+        Terminal::StringConstant(_) => "stringConstant",
+        Terminal::Identifier(_) => "identifier",
+    }
+}
+
+// A terminal on a single line, with spaces around the text like the course files
+fn element(tag: &str, text: impl Display) -> String {
+    let text = text
+        .to_string()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+    format!("<{tag}> {text} </{tag}>")
+}
+
+/** Indented output where every element is on its own line */
+#[derive(Default)]
+struct Writer {
+    res: String,
+    depth: usize,
+}
+
+impl Writer {
+    fn line(&mut self, line: &str) {
+        self.res.push_str(&"  ".repeat(self.depth));
+        self.res.push_str(line);
+        self.res.push('\n');
+    }
+
+    fn open(&mut self, tag: &str) {
+        self.line(&format!("<{tag}>"));
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.line(&format!("</{tag}>"));
+    }
+
+    fn keyword(&mut self, keyword: Keyword) {
+        self.line(&element("keyword", keyword));
+    }
+
+    fn symbol(&mut self, symbol: impl Display) {
+        self.line(&element("symbol", symbol));
+    }
+
+    fn identifier(&mut self, identifier: &Identifier) {
+        self.line(&element("identifier", identifier));
+    }
+
+    fn class(&mut self, class: &Class) {
+        self.open("class");
+        self.keyword(Keyword::Class);
+        self.identifier(&class.0);
+        self.symbol('{');
+        class.1.iter().for_each(|x| self.class_var_dec(x));
+        class.2.iter().for_each(|x| self.subroutine_dec(x));
+        self.symbol('}');
+        self.close("class");
+    }
+
+    fn class_var_dec(&mut self, class_var_dec: &ClassVarDec) {
+        self.open("classVarDec");
+        self.keyword(match class_var_dec.0 {
+            ClassVarType::Static => Keyword::Static,
+            ClassVarType::Field => Keyword::Field,
+        });
+        self.var_names(&class_var_dec.1, &class_var_dec.2);
+        self.close("classVarDec");
+    }
+
+    // type varName (',' varName)* ';'
+    fn var_names(&mut self, var_type: &Type, names: &[Identifier]) {
+        self.var_type(var_type);
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.identifier(name);
+        }
+        self.symbol(';');
+    }
+
+    fn var_type(&mut self, var_type: &Type) {
+        match var_type {
+            Type::Int => self.keyword(Keyword::Int),
+            Type::Char => self.keyword(Keyword::Char),
+            Type::Boolean => self.keyword(Keyword::Boolean),
+            Type::ClassName(name) => self.identifier(name),
+        }
+    }
+
+    fn subroutine_dec(&mut self, subroutine: &SubroutineDec) {
+        self.open("subroutineDec");
+        self.keyword(match subroutine.0 {
+            SubroutineType::Constructor => Keyword::Constructor,
+            SubroutineType::Function => Keyword::Function,
+            SubroutineType::Method => Keyword::Method,
+        });
+        match &subroutine.1 {
+            ReturnType::Void => self.keyword(Keyword::Void),
+            ReturnType::Base(return_type) => self.var_type(return_type),
+        }
+        self.identifier(&subroutine.2);
+        self.symbol('(');
+        self.parameter_list(&subroutine.3);
+        self.symbol(')');
+        self.subroutine_body(&subroutine.4);
+        self.close("subroutineDec");
+    }
+
+    fn parameter_list(&mut self, parameters: &ParameterList) {
+        self.open("parameterList");
+        for (i, parameter) in parameters.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.var_type(&parameter.0);
+            self.identifier(&parameter.1);
+        }
+        self.close("parameterList");
+    }
+
+    fn subroutine_body(&mut self, body: &SubroutineBody) {
+        self.open("subroutineBody");
+        self.symbol('{');
+        body.0.iter().for_each(|x| self.var_dec(x));
+        self.statements(&body.1);
+        self.symbol('}');
+        self.close("subroutineBody");
+    }
+
+    fn var_dec(&mut self, var_dec: &VarDec) {
+        self.open("varDec");
+        self.keyword(Keyword::Var);
+        self.var_names(&var_dec.0, &var_dec.1);
+        self.close("varDec");
+    }
+
+    fn statements(&mut self, statements: &Statements) {
+        self.open("statements");
+        statements.iter().for_each(|x| self.statement(x));
+        self.close("statements");
+    }
+
+    // Statements between braces, after the condition of an if or a while
+    fn block(&mut self, statements: &Statements) {
+        self.symbol('{');
+        self.statements(statements);
+        self.symbol('}');
+    }
+
+    fn parenthesized(&mut self, expression: &Expression) {
+        self.symbol('(');
+        self.expression(expression);
+        self.symbol(')');
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::LetStatement(statement) => {
+                self.open("letStatement");
+                self.keyword(Keyword::Let);
+                self.identifier(&statement.0);
+                if let Some(index) = &statement.1 {
+                    self.symbol('[');
+                    self.expression(index);
+                    self.symbol(']');
+                }
+                self.symbol('=');
+                self.expression(&statement.2);
+                self.symbol(';');
+                self.close("letStatement");
+            }
+            Statement::IfStatement(statement) => {
+                self.open("ifStatement");
+                self.keyword(Keyword::If);
+                self.parenthesized(&statement.0);
+                self.block(&statement.1);
+                if let Some(else_statements) = &statement.2 {
+                    self.keyword(Keyword::Else);
+                    self.block(else_statements);
+                }
+                self.close("ifStatement");
+            }
+            Statement::WhileStatement(statement) => {
+                self.open("whileStatement");
+                self.keyword(Keyword::While);
+                self.parenthesized(&statement.0);
+                self.block(&statement.1);
+                self.close("whileStatement");
+            }
+            Statement::DoStatement(statement) => {
+                // The call is not wrapped in an expression
+                self.open("doStatement");
+                self.keyword(Keyword::Do);
+                self.subroutine_call(&statement.0);
+                self.symbol(';');
+                self.close("doStatement");
+            }
+            Statement::ReturnStatement(statement) => {
+                self.open("returnStatement");
+                self.keyword(Keyword::Return);
+                if let Some(expression) = &statement.0 {
+                    self.expression(expression);
+                }
+                self.symbol(';');
+                self.close("returnStatement");
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.open("expression");
+        self.term(&expression.0);
+        for op_term in &expression.1 {
+            self.symbol(op_term.0);
+            self.term(&op_term.1);
+        }
+        self.close("expression");
+    }
+
+    fn term(&mut self, term: &Term) {
+        self.open("term");
+        match term {
+            Term::IntegerConstant(value, _) => self.line(&element("integerConstant", value)),
+            Term::StringConstant(value, _) => self.line(&element("stringConstant", value)),
+            Term::KeywordConstant(constant, _) => self.keyword(match constant {
+                KeywordConstant::True => Keyword::True,
+                KeywordConstant::False => Keyword::False,
+                KeywordConstant::Null => Keyword::Null,
+                KeywordConstant::This => Keyword::This,
+            }),
+            Term::VarName(name) => self.identifier(name),
+            Term::VarNameIndex(name, index, _) => {
+                self.identifier(name);
+                self.symbol('[');
+                self.expression(index);
+                self.symbol(']');
+            }
+            Term::BracketExpression(expression, _) => self.parenthesized(expression),
+            Term::UnaryOpTerm(op, term, _) => {
+                self.symbol(match op {
+                    UnaryOp::Minus => '-',
+                    UnaryOp::Not => '~',
+                });
+                self.term(term);
+            }
+            Term::SubroutineCall(call) => self.subroutine_call(call),
+        }
+        self.close("term");
+    }
+
+    // (className | varName) '.' subroutineName '(' expressionList ')', or without the receiver
+    fn subroutine_call(&mut self, call: &SubroutineCall) {
+        if let Some(receiver) = &call.0 {
+            self.identifier(receiver);
+            self.symbol('.');
+        }
+        self.identifier(&call.1);
+        self.symbol('(');
+        self.open("expressionList");
+        for (i, expression) in call.2.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.expression(expression);
+        }
+        self.close("expressionList");
+        self.symbol(')');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::source::SourceMap;
+    use std::fs::read_to_string;
+
+    // The course compare files of project 10, with Unix line endings
+    fn compare_file(name: &str) -> String {
+        read_to_string(format!("test/compare/{name}")).unwrap()
+    }
+
+    #[test]
+    fn course_compare_files() {
+        let mut sources = SourceMap::new();
+        let path = "test/Square/SquareGame.jack";
+        let content = read_to_string(path).unwrap().chars().collect();
+        let file = sources.add(path.into(), content);
+        let content = &sources.get(file).unwrap().content;
+        let tokens = Lexer::new(content, file).collect::<Vec<_>>();
+        assert_eq!(super::tokens(&tokens), compare_file("SquareGameT.xml"));
+        let class = Parser::new(content, file).parse().0.unwrap();
+        assert_eq!(super::class(&class), compare_file("SquareGame.xml"));
+    }
+}